@@ -0,0 +1,19 @@
+//! Feeds random fields into the tokenizer and the parser, they must return an error instead of panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use minicel_rs::{parser::Parser, tokenizer};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(field) = std::str::from_utf8(data) else {
+        return;
+    };
+    // The header names of the header-name references. e.g. `$age`
+    let header = ["age".to_owned(), "first name".to_owned()];
+    if let Ok(tokens) = tokenizer::tokenize(field, 1, ';', '$') {
+        let _ = Parser::new(multipeek::multipeek(tokens.iter()), 1)
+            .with_header(&header, 1)
+            .parse();
+    }
+});
@@ -1,4 +1,9 @@
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::{config::EngineConfig, value::ErrorCode};
+
+/// The byte range of a token or an expression in the formula.
+pub type Span = std::ops::Range<usize>;
 
 /// The expressions.
 #[derive(Debug, Clone)]
@@ -11,6 +16,13 @@ pub enum Expression {
         row: u64,
         value: String,
     },
+    /// A range of fields. e.g. `a1:b3`, the start is the top left corner and the end the bottom right one.
+    Range {
+        start_col: String,
+        start_row: u64,
+        end_col: String,
+        end_row: u64,
+    },
     /// A number. e.g. `1` or `-2` or `1.10`
     Number(BigDecimal),
     /// A string. e.g. `"hello"`
@@ -19,6 +31,8 @@ pub enum Expression {
     Boolean(bool),
     /// A array. e.g. `[1, 2, 3, add(a1, a2)]`
     Array(Vec<Expression>),
+    /// The error of a cell that failed to evaluate, the aggregates skip it with `skip_errors`.
+    Error(ErrorCode),
 }
 
 /// The function call expression.
@@ -27,6 +41,10 @@ pub struct FunctionCallExpression {
     pub name: String,
     pub arguments: Vec<Expression>,
     pub line_number: usize,
+    /// The span of the function call, from its name to its right parenthesis
+    pub span: Span,
+    /// The spans of the arguments, the fields of an expanded range have the range span
+    pub argument_spans: Vec<Span>,
 }
 
 /// The AST of the field.
@@ -35,7 +53,82 @@ pub struct Ast {
     pub function: FunctionCallExpression,
 }
 
+impl FunctionCallExpression {
+    /// Returns whether the function, or a function called in its arguments, is not a builtin function.
+    pub fn calls_unknown_function(&self) -> bool {
+        fn calls_unknown_function(expression: &Expression) -> bool {
+            match expression {
+                Expression::FunctionCall(function_call) => function_call.calls_unknown_function(),
+                Expression::Array(array) => array.iter().any(calls_unknown_function),
+                _ => false,
+            }
+        }
+
+        !crate::builtins::is_builtin(&self.name)
+            || self.arguments.iter().any(calls_unknown_function)
+    }
+
+    /// Returns the innermost argument, or argument of a nested function call, containing the given
+    /// byte offset of the formula. The elements of an array are not spanned, the array is the node of
+    /// the offsets inside it.
+    pub fn argument_at_offset(&self, offset: usize) -> Option<&Expression> {
+        let (argument, _) = self
+            .arguments
+            .iter()
+            .zip(&self.argument_spans)
+            .find(|(_, span)| span.contains(&offset))?;
+        match argument {
+            Expression::FunctionCall(function_call) => {
+                function_call.argument_at_offset(offset).or(Some(argument))
+            }
+            argument => Some(argument),
+        }
+    }
+
+    /// Returns whether the function, or a function called in its arguments, can return different
+    /// results for the same arguments.
+    pub fn calls_nondeterministic_function(&self) -> bool {
+        fn calls_nondeterministic_function(expression: &Expression) -> bool {
+            match expression {
+                Expression::FunctionCall(function_call) => {
+                    function_call.calls_nondeterministic_function()
+                }
+                Expression::Array(array) => array.iter().any(calls_nondeterministic_function),
+                _ => false,
+            }
+        }
+
+        crate::builtins::NONDETERMINISTIC_BUILTINS.contains(&self.name.as_str())
+            || self.arguments.iter().any(calls_nondeterministic_function)
+    }
+
+    /// Returns the referenced fields of the function call and its arguments, as (col, row).
+    pub fn referenced_fields(&self) -> Vec<(String, u64)> {
+        let mut fields = Vec::new();
+        let mut expressions = self.arguments.iter().collect::<Vec<_>>();
+        while let Some(expression) = expressions.pop() {
+            match expression {
+                Expression::FunctionCall(function_call) => {
+                    expressions.extend(function_call.arguments.iter())
+                }
+                Expression::Array(array) => expressions.extend(array.iter()),
+                Expression::Field { col, row, .. } => fields.push((col.clone(), *row)),
+                Expression::Range { .. } => fields.extend(expression.range_fields()),
+                _ => {}
+            }
+        }
+        fields
+    }
+}
+
 impl Ast {
+    /// Returns the innermost expression containing the given byte offset of the formula, `None` if
+    /// the offset is outside of the arguments of the formula function. See
+    /// [`FunctionCallExpression::argument_at_offset`].
+    pub fn node_at_offset(&self, offset: usize) -> Option<&Expression> {
+        self.function.argument_at_offset(offset)
+    }
+
     /// Returns the children of the AST.
     pub fn mut_children(&mut self) -> Vec<&mut Expression> {
         let mut children = Vec::new();
@@ -55,6 +148,111 @@ impl Expression {
         }
     }
 
+    /// Returns the number of the expression. if expression is not a number, returns None.
+    pub fn as_number(&self) -> Option<&BigDecimal> {
+        match self {
+            Expression::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    /// Returns the string of the expression. if expression is not a string, returns None.
+    pub fn as_string(&self) -> Option<&str> {
+        match self {
+            Expression::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean of the expression. if expression is not a boolean, returns None.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Expression::Boolean(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    /// Returns the expression as a number, the numeric strings are numbers if `coerce_text_numbers` is set.
+    pub fn to_number(&self, config: &EngineConfig) -> Result<BigDecimal, String> {
+        match self {
+            Expression::Number(number) => Ok(number.clone()),
+            Expression::String(string) if config.coerce_text_numbers => {
+                crate::utils::parse_number(string.trim())
+                    .ok_or_else(|| format!("Expected a number, found the string `{string}`"))
+            }
+            Expression::String(string) => {
+                Err(format!("Expected a number, found the string `{string}`"))
+            }
+            expression => Err(format!("Expected a number, found `{expression}`")),
+        }
+    }
+
+    /// Returns the expression as a boolean, the strings `true` and `false` are booleans too (the nested
+    /// builtin results are strings), `boolean_cells` only changes how the referenced cells are read.
+    /// The numbers `0` and `1` and the strings `yes` and `no` are booleans if `coerce_bool` is set.
+    pub fn to_bool(&self, config: &EngineConfig) -> Result<bool, String> {
+        match self {
+            Expression::Boolean(boolean) => Ok(*boolean),
+            Expression::Number(number)
+                if config.coerce_bool && (number.is_zero() || number == &BigDecimal::from(1)) =>
+            {
+                Ok(!number.is_zero())
+            }
+            Expression::String(string)
+                if config.coerce_bool && ["yes", "no"].contains(&string.as_str()) =>
+            {
+                Ok(string == "yes")
+            }
+            Expression::String(string) if ["true", "false"].contains(&string.as_str()) => {
+                Ok(string == "true")
+            }
+            expression => Err(format!("Expected a boolean, found `{expression}`")),
+        }
+    }
+
+    /// Returns the fields of the range in the row-major order, as (col, row). if expression is not a
+    /// range, returns an empty vector.
+    pub fn range_fields(&self) -> Vec<(String, u64)> {
+        let Expression::Range {
+            start_col,
+            start_row,
+            end_col,
+            end_row,
+        } = self
+        else {
+            return Vec::new();
+        };
+        let (Some(start_col), Some(end_col)) = (
+            crate::utils::col_number_from_alpha(start_col),
+            crate::utils::col_number_from_alpha(end_col),
+        ) else {
+            return Vec::new();
+        };
+        let cols = start_col..=end_col;
+        (*start_row..=*end_row)
+            .flat_map(|row| {
+                cols.clone()
+                    .map(move |col| (crate::utils::col_alpha_from_number(col), row))
+            })
+            .collect()
+    }
+
+    /// Returns the fields of the expression if it is a range, otherwise the expression itself.
+    pub fn expand_range(self) -> Vec<Expression> {
+        match self {
+            Expression::Range { .. } => self
+                .range_fields()
+                .into_iter()
+                .map(|(col, row)| Expression::Field {
+                    col,
+                    row,
+                    value: String::new(),
+                })
+                .collect(),
+            expression => vec![expression],
+        }
+    }
+
     /// Returns the children of the expression.
     pub fn mut_children(&mut self) -> Vec<&mut Expression> {
         let mut children = Vec::new();
@@ -77,6 +275,12 @@ impl Expression {
     }
 }
 
+impl From<String> for Expression {
+    fn from(string: String) -> Self {
+        Expression::String(string)
+    }
+}
+
 impl std::fmt::Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -104,9 +308,20 @@ impl std::fmt::Display for Expression {
                 row: _,
                 value,
             } => write!(f, "{value}"),
-            Expression::Number(number) => write!(f, "{}", number),
+            Expression::Range {
+                start_col,
+                start_row,
+                end_col,
+                end_row,
+            } => write!(f, "{start_col}{start_row}:{end_col}{end_row}"),
+            Expression::Number(number) => write!(
+                f,
+                "{}",
+                crate::utils::format_number(number, crate::utils::DEFAULT_PLAIN_NUMBER_LIMIT)
+            ),
             Expression::String(string) => write!(f, "{}", string),
             Expression::Boolean(boolean) => write!(f, "{}", boolean),
+            Expression::Error(code) => write!(f, "{}", code.as_str()),
             Expression::Array(array) => {
                 write!(f, "[")?;
                 for (i, element) in array.iter().enumerate() {
@@ -120,3 +335,106 @@ impl std::fmt::Display for Expression {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_bool_accepts_the_booleans_and_their_strings() {
+        let config = EngineConfig::default();
+        assert_eq!(Expression::Boolean(true).to_bool(&config), Ok(true));
+        assert_eq!(
+            Expression::String("false".into()).to_bool(&config),
+            Ok(false)
+        );
+        assert!(Expression::String("yes".into()).to_bool(&config).is_err());
+        assert!(Expression::Number(1.into()).to_bool(&config).is_err());
+    }
+
+    #[test]
+    fn to_bool_accepts_the_strings_without_boolean_cells() {
+        let config = EngineConfig {
+            boolean_cells: false,
+            ..EngineConfig::default()
+        };
+        assert_eq!(Expression::String("true".into()).to_bool(&config), Ok(true));
+        assert_eq!(
+            Expression::String("false".into()).to_bool(&config),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn to_bool_coerces_with_coerce_bool() {
+        let config = EngineConfig {
+            coerce_bool: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(Expression::Number(0.into()).to_bool(&config), Ok(false));
+        assert_eq!(Expression::String("yes".into()).to_bool(&config), Ok(true));
+        assert!(Expression::Number(2.into()).to_bool(&config).is_err());
+    }
+
+    /// One expression of each literal variant, and an array.
+    fn variants() -> [Expression; 4] {
+        [
+            Expression::Number(BigDecimal::from(2)),
+            Expression::String("text".into()),
+            Expression::Boolean(true),
+            Expression::Array(vec![Expression::Number(BigDecimal::from(2))]),
+        ]
+    }
+
+    #[test]
+    fn as_number_matches_only_the_numbers() {
+        let [number, string, boolean, array] = variants();
+        assert_eq!(number.as_number(), Some(&BigDecimal::from(2)));
+        assert_eq!(string.as_number(), None);
+        assert_eq!(boolean.as_number(), None);
+        assert_eq!(array.as_number(), None);
+    }
+
+    #[test]
+    fn as_string_matches_only_the_strings() {
+        let [number, string, boolean, array] = variants();
+        assert_eq!(number.as_string(), None);
+        assert_eq!(string.as_string(), Some("text"));
+        assert_eq!(boolean.as_string(), None);
+        assert_eq!(array.as_string(), None);
+    }
+
+    #[test]
+    fn as_bool_matches_only_the_booleans() {
+        let [number, string, boolean, array] = variants();
+        assert_eq!(number.as_bool(), None);
+        assert_eq!(string.as_bool(), None);
+        assert_eq!(boolean.as_bool(), Some(true));
+        assert_eq!(array.as_bool(), None);
+    }
+
+    #[test]
+    fn to_number_coerces_the_numeric_strings_with_coerce_text_numbers() {
+        let config = EngineConfig::default();
+        let numeric = Expression::String(" 1.5 ".into());
+        assert_eq!(
+            Expression::Number(BigDecimal::from(2)).to_number(&config),
+            Ok(BigDecimal::from(2))
+        );
+        assert!(numeric.to_number(&config).is_err());
+        assert!(Expression::Boolean(true).to_number(&config).is_err());
+
+        let config = EngineConfig {
+            coerce_text_numbers: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            numeric.to_number(&config),
+            Ok(BigDecimal::new(15.into(), 1))
+        );
+        assert_eq!(
+            Expression::String("1.5x".into()).to_number(&config),
+            Err("Expected a number, found the string `1.5x`".to_owned())
+        );
+    }
+}
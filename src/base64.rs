@@ -0,0 +1,102 @@
+//! The standard base64 (RFC 4648) encoding, with padding.
+
+/// The base64 alphabet.
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes the given bytes to base64.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = match chunk {
+            [a] => u32::from(*a) << 16,
+            [a, b] => u32::from(*a) << 16 | u32::from(*b) << 8,
+            [a, b, c] => u32::from(*a) << 16 | u32::from(*b) << 8 | u32::from(*c),
+            _ => unreachable!("chunks of 3"),
+        };
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - i * 6) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes the given base64 string.
+pub fn decode(encoded: &str) -> Result<Vec<u8>, String> {
+    let encoded = encoded.as_bytes();
+    if encoded.len() % 4 != 0 {
+        return Err(format!(
+            "Invalid base64 length {}, expected a multiple of 4",
+            encoded.len()
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let chunks_count = encoded.len() / 4;
+    for (chunk_idx, chunk) in encoded.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && chunk_idx != chunks_count - 1) {
+            return Err("Invalid base64 padding".to_owned());
+        }
+
+        let mut group = 0u32;
+        for c in &chunk[..4 - padding] {
+            let value = ALPHABET
+                .iter()
+                .position(|a| a == c)
+                .ok_or_else(|| format!("Invalid base64 character `{}`", *c as char))?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding as u32;
+        bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 4648 section 10 test vectors, every padding length.
+    const VECTORS: &[(&str, &str)] = &[
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn encodes_the_test_vectors() {
+        for (bytes, encoded) in VECTORS {
+            assert_eq!(encode(bytes.as_bytes()), *encoded);
+        }
+    }
+
+    #[test]
+    fn decodes_the_test_vectors() {
+        for (bytes, encoded) in VECTORS {
+            assert_eq!(decode(encoded).as_deref(), Ok(bytes.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn round_trips_all_the_bytes() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        for len in 0..bytes.len() {
+            assert_eq!(decode(&encode(&bytes[..len])), Ok(bytes[..len].to_vec()));
+        }
+    }
+
+    #[test]
+    fn rejects_the_invalid_input() {
+        for encoded in ["Zm9", "Zm9vY", "Zm9*", "Zm=v", "Z===", "Zg==Zm8=", "Zm 9v"] {
+            assert!(decode(encoded).is_err(), "{encoded}");
+        }
+    }
+}
@@ -1,78 +1,2207 @@
-use crate::ast::Expression;
+use std::{collections::HashMap, str::FromStr};
+
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive, Zero};
+
+use crate::{
+    ast::Expression,
+    base64,
+    config::{self, EngineConfig},
+    constants,
+    date::Date,
+    digest, json,
+    rng::Rng,
+    utils,
+    value::ErrorCode,
+};
 
 pub type FunctionResult = Result<String, String>;
 
-/// Call the builtin function. syntax: call_builtin![builtin_function1, builtin_function2, (name, args)]
-/// Wich name is the name of the called function and args is the arguments of the function.
-///
-/// The builtin function must return a [`FunctionResult`].
-macro_rules! call_builtin {
-    ($($builtin_function: ident),+, ($call_name: ident, $call_args: ident)) => {
-        match $call_name {
-            $(
-                stringify!($builtin_function) => Some($builtin_function($call_args)),
-            )+
-            _ => {
-                log::error!("No builtin function found with name: {}", $call_name);
-                None
-            },
+/// The error of a builtin function call, with the error code of the cell that failed with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltinError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl BuiltinError {
+    /// Returns a `#DIV/0!` error with the given message.
+    pub fn division_by_zero(message: &str) -> Self {
+        Self {
+            code: ErrorCode::DivisionByZero,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl From<String> for BuiltinError {
+    /// Returns a `#VALUE!` error with the given message.
+    fn from(message: String) -> Self {
+        Self {
+            code: ErrorCode::InvalidValue,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for BuiltinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The typed result of a builtin function call, the text results are strings.
+pub type BuiltinResult = Result<Expression, BuiltinError>;
+
+/// The engine state that the builtin functions can use.
+#[derive(Debug)]
+pub struct Context<'a> {
+    /// The engine configuration
+    pub config: &'a EngineConfig,
+    /// The engine random number generator
+    pub rng: &'a mut Rng,
+}
+
+/// Returns the given function as a [`BuiltinFunction`], the function returns a [`FunctionResult`] or
+/// any result that converts to a [`BuiltinResult`], e.g. an array or a [`BuiltinError`].
+macro_rules! builtin_function {
+    ($function: path) => {
+        (|args: Vec<Expression>, ctx: &mut Context| {
+            $function(args, ctx).map(Into::into).map_err(Into::into)
+        }) as BuiltinFunction
+    };
+}
+
+/// Declares the builtin functions from one list: the [`BUILTINS`] signatures and descriptions, and
+/// the registry of their functions for the dispatch. syntax:
+/// builtins! { "name" => function: "signature", "description"; }
+/// Where the function is converted with [`builtin_function`], a builtin without a function
+/// (e.g. `iferror`) is evaluated by the engine.
+macro_rules! builtins {
+    ($($name: literal $(=> $function: ident)?: $signature: literal, $description: literal;)+) => {
+        /// The builtin functions. (name, signature, description)
+        pub const BUILTINS: &[(&str, &str, &str)] = &[$(($name, $signature, $description)),+];
+
+        thread_local! {
+            /// The builtin functions by name, built once per thread for the O(1) dispatch.
+            static REGISTRY: HashMap<&'static str, BuiltinFunction> = HashMap::from([
+                $($(($name, builtin_function!($function)),)?)+
+            ]);
+
+            /// The builtin functions signatures by name, for the type checks of their arguments.
+            static SIGNATURES: HashMap<&'static str, &'static str> =
+                HashMap::from([$(($name, $signature)),+]);
         }
     };
 }
 
-pub fn call_builtin(name: &str, args: Vec<Expression>) -> Option<FunctionResult> {
+builtins! {
+    "print" => print: "print(any...)", "Prints the arguments to the cell";
+    "sum" => sum: "sum(number...)", "Sums the arguments and their array elements";
+    "sub" => sub: "sub(number; number)", "Subtracts the second argument from the first argument";
+    "mul" => mul: "mul(number; number)", "Multiplies the two arguments";
+    "div" => div: "div(number; number)", "Divides the first argument by the second argument";
+    "sha256" => sha256: "sha256(any)", "Returns the SHA-256 hex digest of the argument";
+    "md5" => md5: "md5(any)", "Returns the MD5 hex digest of the argument";
+    "base64_encode" => base64_encode: "base64_encode(any)", "Encodes the argument to base64";
+    "base64_decode" => base64_decode: "base64_decode(string)", "Decodes the base64 argument";
+    "json_extract" => json_extract:
+        "json_extract(string; string)",
+        "Extracts the value at the path from the JSON argument";
+    "iferror":
+        "iferror(any; any)",
+        "Returns the first argument, or the second argument if the first one fails";
+    "contains" => contains:
+        "contains(any; any)",
+        "Returns whether the first argument contains the second argument";
+    "icontains" => icontains: "icontains(any; any)", "Same as `contains`, but case-insensitive";
+    "startswith" => startswith:
+        "startswith(any; any)",
+        "Returns whether the first argument starts with the second argument";
+    "istartswith" => istartswith:
+        "istartswith(any; any)",
+        "Same as `startswith`, but case-insensitive";
+    "endswith" => endswith:
+        "endswith(any; any)",
+        "Returns whether the first argument ends with the second argument";
+    "iendswith" => iendswith: "iendswith(any; any)", "Same as `endswith`, but case-insensitive";
+    "year" => year: "year(string)", "Returns the year of the ISO date";
+    "month" => month: "month(string)", "Returns the month of the ISO date";
+    "day" => day: "day(string)", "Returns the day of the ISO date";
+    "weekday" => weekday:
+        "weekday(string; number?)",
+        "Returns the weekday of the ISO date, 1 is the given first day (1 is Monday by default)";
+    "padleft" => padleft:
+        "padleft(any; number; string)",
+        "Pads the start of the first argument to the width with the fill character";
+    "padright" => padright:
+        "padright(any; number; string)",
+        "Pads the end of the first argument to the width with the fill character";
+    "sumproduct" => sumproduct:
+        "sumproduct(array; array)",
+        "Sums the products of the corresponding elements of the two arrays";
+    "distinct_count_if" => distinct_count_if:
+        "distinct_count_if(array; string; any)",
+        "Counts the distinct array elements matching the condition";
+    "textjoin" => textjoin:
+        "textjoin(string; boolean; any...)",
+        "Joins the arguments and the array elements with the separator, optionally ignoring the empty ones";
+    "concat_if" => concat_if:
+        "concat_if(array; string; any; string; array?)",
+        "Joins the array elements matching the condition, or the corresponding elements of the values array, with the separator";
+    "gcd" => gcd: "gcd(number; number)", "Returns the greatest common divisor of the two integers";
+    "lcm" => lcm: "lcm(number; number)", "Returns the least common multiple of the two integers";
+    "round" => round:
+        "round(number; number?; string?)",
+        "Rounds the number to the given decimal digits (0 by default) with the given rounding mode";
+    "mode" => mode:
+        "mode(array)",
+        "Returns the most frequent array element, the first seen one on a tie";
+    "avg" => avg: "avg(number...)", "Returns the average of the arguments and their array elements";
+    "min" => min:
+        "min(number...)",
+        "Returns the smallest of the arguments and their array elements";
+    "max" => max: "max(number...)", "Returns the largest of the arguments and their array elements";
+    "if" => if_:
+        "if(boolean; any; any)",
+        "Returns the second argument if the first one is true, otherwise the third argument";
+    "eq" => eq:
+        "eq(any; any)",
+        "Returns whether the two arguments are equal, the numbers are compared numerically";
+    "gt" => gt:
+        "gt(number; number)",
+        "Returns whether the first argument is greater than the second argument";
+    "lt" => lt:
+        "lt(number; number)",
+        "Returns whether the first argument is less than the second argument";
+    "gte" => gte:
+        "gte(number; number)",
+        "Returns whether the first argument is greater than or equal to the second argument";
+    "lte" => lte:
+        "lte(number; number)",
+        "Returns whether the first argument is less than or equal to the second argument";
+    "pi" => pi: "pi()", "Returns the π constant, rounded to the constants precision";
+    "e" => e: "e()", "Returns the e constant, rounded to the constants precision";
+    "and" => and:
+        "and(boolean...)",
+        "Returns whether all the arguments are true, `true` without arguments";
+    "or" => or:
+        "or(boolean...)",
+        "Returns whether any of the arguments is true, `false` without arguments";
+    "not" => not: "not(boolean)", "Returns the negation of the argument";
+    "concat" => concat:
+        "concat(any...)",
+        "Joins the arguments and their array elements without a separator";
+    "len" => len: "len(any)", "Returns the number of characters of the argument";
+    "substr" => substr:
+        "substr(any; number; number)",
+        "Returns the characters of the first argument from the start (Starting from 1) with the length";
+    "mid" => substr: "mid(any; number; number)", "Same as `substr`";
+    "repeat" => repeat:
+        "repeat(any; number)",
+        "Repeats the first argument the given number of times";
+    "join" => join: "join(array; any)", "Joins the array elements with the separator";
+    "shuffle" => shuffle: "shuffle(array)", "Returns the array elements in a random order";
+    "rand" => rand: "rand()", "Returns a random number in [0, 1)";
+}
+
+/// The builtin functions that can return different results for the same arguments.
+pub const NONDETERMINISTIC_BUILTINS: &[&str] = &["shuffle", "rand"];
+
+/// The aggregate builtin functions that skip the error cells with `skip_errors`.
+pub const ERROR_SKIPPING_BUILTINS: &[&str] = &["sum", "avg", "min", "max"];
+
+/// A builtin function.
+pub type BuiltinFunction = fn(Vec<Expression>, &mut Context) -> BuiltinResult;
+
+pub fn call_builtin(name: &str, args: Vec<Expression>, ctx: &mut Context) -> Option<BuiltinResult> {
     log::debug!("Trying to call builtin function: {name} with args: {args:?}");
-    call_builtin![print, sum, sub, mul, div, (name, args)]
+    let Some(builtin_function) = REGISTRY.with(|registry| registry.get(name).copied()) else {
+        log::debug!("No builtin function found with name: {name}");
+        return None;
+    };
+    if ctx.config.deterministic
+        && ctx.config.seed.is_none()
+        && NONDETERMINISTIC_BUILTINS.contains(&name)
+    {
+        return Some(Err(format!(
+            "The {name} function is nondeterministic, it requires a seed (`--seed`) with `--deterministic`"
+        )
+        .into()));
+    }
+    Some(builtin_function(args, ctx).and_then(|result| {
+        let length = match &result {
+            Expression::String(string) => string.chars().count(),
+            result => result.to_string().chars().count(),
+        };
+        check_string_length(length, ctx)?;
+        Ok(result)
+    }))
 }
 
 pub fn is_builtin(name: &str) -> bool {
-    ["print", "sum", "sub", "mul", "div"].contains(&name)
+    BUILTINS.iter().any(|(builtin, _, _)| builtin == &name)
 }
 
-pub fn print(args: Vec<Expression>) -> FunctionResult {
-    Ok(args
+/// Returns the builtin function with the closest name to the given unknown name, if it's close
+/// enough to be a misspelling of it.
+pub fn closest_builtin(name: &str) -> Option<&'static str> {
+    BUILTINS
+        .iter()
+        .map(|(builtin, _, _)| (*builtin, utils::edit_distance(name, builtin)))
+        .filter(|(_, distance)| *distance <= 2 && distance * 2 <= name.chars().count())
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(builtin, _)| builtin)
+}
+
+/// Returns the parameter types of the builtin function signature, e.g. `["number", "number..."]`.
+fn parameter_types(name: &str) -> Option<Vec<&'static str>> {
+    let signature = SIGNATURES.with(|signatures| signatures.get(name).copied())?;
+    let parameters = signature
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    Some(
+        parameters
+            .split(';')
+            .map(str::trim)
+            .filter(|parameter| !parameter.is_empty())
+            .collect(),
+    )
+}
+
+/// Returns whether the argument can't be of the parameter type (without its `...` and `?`). The
+/// arrays of a number are checked element by element, the fields and the function calls are not
+/// known before the evaluation and are never mistyped.
+fn is_mistyped(arg: &Expression, parameter_type: &str, config: &EngineConfig) -> bool {
+    match (parameter_type, arg) {
+        ("number", Expression::Array(array)) => array
+            .iter()
+            .any(|element| is_mistyped(element, parameter_type, config)),
+        ("number", Expression::String(_) | Expression::Boolean(_)) => {
+            arg.to_number(config).is_err()
+        }
+        (
+            "boolean",
+            Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Boolean(_)
+            | Expression::Array(_),
+        ) => arg.to_bool(config).is_err(),
+        ("array", Expression::Number(_) | Expression::String(_) | Expression::Boolean(_)) => true,
+        _ => false,
+    }
+}
+
+/// Returns the indices of the arguments that can't be of their parameter type in the builtin
+/// function signature, with the expected type (e.g. `a number`). The last parameter of a `...`
+/// signature is the type of the remaining arguments, the `any` and `string` parameters accept all
+/// the arguments.
+pub fn mistyped_arguments(
+    name: &str,
+    args: &[Expression],
+    config: &EngineConfig,
+) -> Vec<(usize, &'static str)> {
+    let Some(parameters) = parameter_types(name) else {
+        return Vec::new();
+    };
+    args.iter()
+        .enumerate()
+        .filter_map(|(index, arg)| {
+            let parameter = parameters
+                .get(index)
+                .or_else(|| parameters.last().filter(|last| last.ends_with("...")))?;
+            let parameter_type = parameter.trim_end_matches("...").trim_end_matches('?');
+            let expected = match parameter_type {
+                "number" => "a number",
+                "boolean" => "a boolean",
+                "array" => "an array",
+                _ => return None,
+            };
+            is_mistyped(arg, parameter_type, config).then_some((index, expected))
+        })
+        .collect()
+}
+
+/// Returns the signatures of the builtin functions, one per line.
+pub fn signatures() -> String {
+    let width = BUILTINS
         .iter()
-        .map(|arg| arg.to_string())
+        .map(|(_, signature, _)| signature.len())
+        .max()
+        .unwrap_or_default();
+    BUILTINS
+        .iter()
+        .map(|(_, signature, description)| format!("{signature:width$}  {description}"))
         .collect::<Vec<_>>()
-        .join(", "))
+        .join("\n")
 }
 
-pub fn sum(args: Vec<Expression>) -> FunctionResult {
+/// Returns the number of the argument, numeric strings are numbers if `coerce_text_numbers` is set.
+fn number_arg(arg: &Expression, ctx: &Context) -> Result<BigDecimal, String> {
+    arg.to_number(ctx.config)
+}
+
+/// Collects the numbers of the arguments, the arrays are flattened.
+/// Numeric strings are numbers if `coerce_text_numbers` is set, and the errors of the failed cells
+/// are skipped if `skip_errors` is set. The strings that look like error codes are not errors.
+pub fn collect_numbers(args: &[Expression], ctx: &Context) -> Result<Vec<BigDecimal>, String> {
+    let mut numbers = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Expression::Array(array) => numbers.extend(collect_numbers(array, ctx)?),
+            Expression::Error(code) if ctx.config.skip_errors => {
+                log::debug!("Skipping the error cell {}", code.as_str());
+            }
+            arg => numbers.push(number_arg(arg, ctx)?),
+        }
+    }
+    Ok(numbers)
+}
+
+/// Returns the two number arguments of a binary function.
+fn binary_numbers(args: &[Expression], ctx: &Context) -> Result<(BigDecimal, BigDecimal), String> {
     if args.len() != 2 {
         return Err(format!("Expected 2 arguments, found {}", args.len()));
     }
-    match (&args[0], &args[1]) {
-        (Expression::Number(n1), Expression::Number(n2)) => Ok((n1 + n2).to_string()),
-        (a1, a2) => Err(format!("Expected numbers found `{a2}` and `{a1}`")),
+    let mut numbers = collect_numbers(args, ctx)?.into_iter();
+    match (numbers.next(), numbers.next(), numbers.next()) {
+        (Some(n1), Some(n2), None) => Ok((n1, n2)),
+        _ => Err(format!(
+            "Expected 2 numbers, found `{}` and `{}`",
+            args[0], args[1]
+        )),
+    }
+}
+
+/// Returns the absolute values of the two integer arguments of a binary function.
+fn binary_integers(args: &[Expression], ctx: &Context) -> Result<(BigDecimal, BigDecimal), String> {
+    let (n1, n2) = binary_numbers(args, ctx)?;
+    if let Some(number) = [&n1, &n2].into_iter().find(|number| !number.is_integer()) {
+        return Err(format!(
+            "Expected an integer, found `{}`",
+            format_number(number, ctx)
+        ));
+    }
+    Ok((n1.abs().with_scale(0), n2.abs().with_scale(0)))
+}
+
+/// Returns the non-negative integer argument, the name is the argument name in the error message.
+fn non_negative_integer(arg: &Expression, name: &str, ctx: &Context) -> Result<usize, String> {
+    let number = number_arg(arg, ctx)?;
+    number
+        .is_integer()
+        .then(|| number.to_usize())
+        .flatten()
+        .ok_or_else(|| {
+            format!(
+                "Invalid {name} `{}`, expected a non-negative integer",
+                format_number(&number, ctx)
+            )
+        })
+}
+
+/// Returns the greatest common divisor of the two non-negative integers. (Euclid's algorithm)
+fn greatest_common_divisor(mut n1: BigDecimal, mut n2: BigDecimal) -> BigDecimal {
+    while !n2.is_zero() {
+        let remainder = &n1 % &n2;
+        n1 = n2;
+        n2 = remainder;
+    }
+    n1
+}
+
+/// Formats the number with the configured plain number limit.
+fn format_number(number: &BigDecimal, ctx: &Context) -> String {
+    utils::format_number(number, ctx.config.plain_number_limit)
+}
+
+/// Returns the arguments with their arrays flattened, in order.
+fn flatten_args(args: &[Expression]) -> Vec<&Expression> {
+    let mut flattened = Vec::new();
+    let mut args = args.iter().rev().collect::<Vec<_>>();
+    while let Some(arg) = args.pop() {
+        match arg {
+            Expression::Array(array) => args.extend(array.iter().rev()),
+            arg => flattened.push(arg),
+        }
     }
+    flattened
+}
+
+/// Formats the argument like its `Display`, with the configured plain number limit.
+fn format_arg(arg: &Expression, ctx: &Context) -> String {
+    match arg {
+        Expression::Number(number) => format_number(number, ctx),
+        Expression::Array(array) => format!(
+            "[{}]",
+            array
+                .iter()
+                .map(|element| format_arg(element, ctx))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        arg => arg.to_string(),
+    }
+}
+
+/// Returns whether the element matches the condition, `element op value`. The operators are
+/// `=`, `!=` (or `<>`), `<`, `<=`, `>` and `>=`, the ordering operators compare numbers only.
+fn matches_condition(
+    element: &Expression,
+    operator: &str,
+    value: &Expression,
+    ctx: &Context,
+) -> Result<bool, String> {
+    let numbers = match (element, value) {
+        (Expression::Number(_), _) | (_, Expression::Number(_)) => number_arg(element, ctx)
+            .ok()
+            .zip(number_arg(value, ctx).ok()),
+        _ => None,
+    };
+    let equal = match &numbers {
+        Some((element, value)) => element == value,
+        None => element.to_string() == value.to_string(),
+    };
+    match operator {
+        "=" => Ok(equal),
+        "!=" | "<>" => Ok(!equal),
+        "<" | "<=" | ">" | ">=" => {
+            let Some((element, value)) = numbers else {
+                return Err(format!(
+                    "The `{operator}` operator compares numbers, found `{element}` and `{value}`"
+                ));
+            };
+            Ok(match operator {
+                "<" => element < value,
+                "<=" => element <= value,
+                ">" => element > value,
+                _ => element >= value,
+            })
+        }
+        operator => Err(format!(
+            "Unknown operator `{operator}`, expected `=`, `!=`, `<>`, `<`, `<=`, `>` or `>=`"
+        )),
+    }
+}
+
+/// Returns the given constant rounded to the `constants_precision` fraction digits.
+fn constant(args: &[Expression], constant: &str, ctx: &Context) -> FunctionResult {
+    if !args.is_empty() {
+        return Err(format!("Expected 0 arguments, found {}", args.len()));
+    }
+    let constant = BigDecimal::from_str(constant).expect("the constants are valid numbers");
+    Ok(format_number(
+        &constant.with_scale_round(ctx.config.constants_precision as i64, RoundingMode::HalfUp),
+        ctx,
+    ))
+}
+
+/// Compares the two arguments with the given operator of [`matches_condition`].
+fn compare(args: &[Expression], operator: &str, ctx: &Context) -> FunctionResult {
+    let [left, right] = args else {
+        return Err(format!("Expected 2 arguments, found {}", args.len()));
+    };
+    matches_condition(left, operator, right, ctx).map(|result| result.to_string())
 }
 
-pub fn sub(args: Vec<Expression>) -> FunctionResult {
+/// Checks the two string arguments with the given predicate, the case-insensitive check lowercases them first.
+fn string_predicate(
+    args: &[Expression],
+    case_insensitive: bool,
+    predicate: fn(&str, &str) -> bool,
+) -> FunctionResult {
     if args.len() != 2 {
         return Err(format!("Expected 2 arguments, found {}", args.len()));
     }
-    match (&args[0], &args[1]) {
-        (Expression::Number(n1), Expression::Number(n2)) => Ok((n1 - n2).to_string()),
-        (a1, a2) => Err(format!("Expected numbers found `{a2}` and `{a1}`")),
+    let (string, pattern) = (args[0].to_string(), args[1].to_string());
+    Ok(if case_insensitive {
+        predicate(&string.to_lowercase(), &pattern.to_lowercase())
+    } else {
+        predicate(&string, &pattern)
+    }
+    .to_string())
+}
+
+/// Parses the ISO date argument of the date part builtin functions.
+fn date_arg(args: &[Expression]) -> Result<Date, String> {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    Date::parse(&args[0].to_string())
+}
+
+/// Checks that the length of a produced string doesn't exceed the configured maximum string length.
+fn check_string_length(length: usize, ctx: &Context) -> Result<(), String> {
+    if length > ctx.config.max_string_length {
+        return Err(format!(
+            "The result is {length} characters long, the maximum string length is {}",
+            ctx.config.max_string_length
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the given texts joined with the separator, the length of the joined string is checked
+/// before it is allocated.
+fn join_texts(texts: &[String], separator: &str, ctx: &Context) -> FunctionResult {
+    let length = texts
+        .iter()
+        .map(|text| text.chars().count())
+        .sum::<usize>()
+        .saturating_add(
+            separator
+                .chars()
+                .count()
+                .saturating_mul(texts.len().saturating_sub(1)),
+        );
+    check_string_length(length, ctx)?;
+    Ok(texts.join(separator))
+}
+
+/// Returns the padding of the pad builtin functions arguments, `(string; width; fill)`.
+fn padding(args: &[Expression], ctx: &Context) -> Result<String, String> {
+    if args.len() != 3 {
+        return Err(format!("Expected 3 arguments, found {}", args.len()));
     }
+    let width = non_negative_integer(&args[1], "width", ctx)?;
+    // Checked before the padding is allocated
+    check_string_length(width, ctx)?;
+    let fill = args[2].to_string();
+    let mut fill_chars = fill.chars();
+    let (Some(fill_char), None) = (fill_chars.next(), fill_chars.next()) else {
+        return Err(format!("Invalid fill `{fill}`, expected one character"));
+    };
+    let length = args[0].to_string().chars().count();
+    Ok(std::iter::repeat(fill_char)
+        .take(width.saturating_sub(length))
+        .collect())
+}
+
+pub fn print(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    Ok(args
+        .iter()
+        .map(|arg| format_arg(arg, ctx))
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+pub fn sum(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    Ok(format_number(
+        &collect_numbers(&args, ctx)?.into_iter().sum::<BigDecimal>(),
+        ctx,
+    ))
+}
+
+pub fn sub(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let (n1, n2) = binary_numbers(&args, ctx)?;
+    Ok(format_number(&(n1 - n2), ctx))
 }
 
-pub fn mul(args: Vec<Expression>) -> FunctionResult {
+pub fn mul(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let (n1, n2) = binary_numbers(&args, ctx)?;
+    Ok(format_number(&(n1 * n2), ctx))
+}
+
+pub fn div(args: Vec<Expression>, ctx: &mut Context) -> Result<String, BuiltinError> {
+    let (n1, n2) = binary_numbers(&args, ctx)?;
+    if n2.is_zero() {
+        return Err(BuiltinError::division_by_zero("Division by zero"));
+    }
+    Ok(format_number(&(n1 / n2), ctx))
+}
+
+pub fn sha256(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    Ok(digest::to_hex(&digest::sha256(
+        args[0].to_string().as_bytes(),
+    )))
+}
+
+pub fn md5(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    Ok(digest::to_hex(&digest::md5(args[0].to_string().as_bytes())))
+}
+
+pub fn base64_encode(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    Ok(base64::encode(args[0].to_string().as_bytes()))
+}
+
+pub fn base64_decode(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    let bytes = base64::decode(&args[0].to_string())?;
+    String::from_utf8(bytes)
+        .map_err(|_| "The decoded base64 is not a valid UTF-8 string".to_owned())
+}
+
+pub fn json_extract(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
     if args.len() != 2 {
         return Err(format!("Expected 2 arguments, found {}", args.len()));
     }
-    match (&args[0], &args[1]) {
-        (Expression::Number(n1), Expression::Number(n2)) => Ok((n1 * n2).to_string()),
-        (a1, a2) => Err(format!("Expected numbers found `{a2}` and `{a1}`")),
+    let value = json::JsonValue::parse(&args[0].to_string())?;
+    let path = args[1].to_string();
+    value
+        .get_path(&json::parse_path(&path)?)
+        .map(json::JsonValue::to_cell_string)
+        .ok_or_else(|| format!("The JSON path `{path}` does not exist"))
+}
+
+pub fn shuffle(args: Vec<Expression>, ctx: &mut Context) -> Result<Expression, String> {
+    if args.len() != 1 {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    }
+    match args.into_iter().next() {
+        Some(Expression::Array(mut array)) => {
+            ctx.rng.shuffle(&mut array);
+            Ok(Expression::Array(array))
+        }
+        Some(arg) => Err(format!("Expected an array found `{arg}`")),
+        None => unreachable!("there is 1 argument"),
     }
 }
 
-pub fn div(args: Vec<Expression>) -> FunctionResult {
-    if args.len() != 2 {
+/// The `rand` builtin function, a random number of 15 fraction digits in `[0, 1)`.
+pub fn rand(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    if !args.is_empty() {
+        return Err(format!("Expected 0 arguments, found {}", args.len()));
+    }
+    let digits = ctx.rng.next_u64() % 10_u64.pow(15);
+    Ok(format_number(
+        &BigDecimal::new(digits.into(), 15).normalized(),
+        ctx,
+    ))
+}
+
+pub fn contains(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, false, |string, pattern| string.contains(pattern))
+}
+
+pub fn icontains(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, true, |string, pattern| string.contains(pattern))
+}
+
+pub fn startswith(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, false, |string, pattern| string.starts_with(pattern))
+}
+
+pub fn istartswith(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, true, |string, pattern| string.starts_with(pattern))
+}
+
+pub fn endswith(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, false, |string, pattern| string.ends_with(pattern))
+}
+
+pub fn iendswith(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    string_predicate(&args, true, |string, pattern| string.ends_with(pattern))
+}
+
+pub fn year(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    Ok(date_arg(&args)?.year.to_string())
+}
+
+pub fn month(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    Ok(date_arg(&args)?.month.to_string())
+}
+
+pub fn day(args: Vec<Expression>, _: &mut Context) -> FunctionResult {
+    Ok(date_arg(&args)?.day.to_string())
+}
+
+/// The weekday of the date, the optional second argument is the first day of the week
+/// (1 is Monday and 7 is Sunday), which is 1.
+pub fn weekday(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let first_day = match args.len() {
+        1 => 1,
+        2 => number_arg(&args[1], ctx)?
+            .to_u32()
+            .filter(|first_day| (1..=7).contains(first_day))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid first day of the week `{}`, expected 1 (Monday) to 7 (Sunday)",
+                    args[1]
+                )
+            })?,
+        len => return Err(format!("Expected 1 or 2 arguments, found {len}")),
+    };
+    let date = date_arg(&args[..1])?;
+    Ok(((date.weekday() + 7 - first_day) % 7 + 1).to_string())
+}
+
+pub fn padleft(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    Ok(padding(&args, ctx)? + &args[0].to_string())
+}
+
+pub fn padright(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let padding = padding(&args, ctx)?;
+    Ok(args[0].to_string() + &padding)
+}
+
+pub fn gcd(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let (n1, n2) = binary_integers(&args, ctx)?;
+    Ok(format_number(&greatest_common_divisor(n1, n2), ctx))
+}
+
+pub fn lcm(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let (n1, n2) = binary_integers(&args, ctx)?;
+    if n1.is_zero() || n2.is_zero() {
+        return Ok("0".to_owned());
+    }
+    let gcd = greatest_common_divisor(n1.clone(), n2.clone());
+    Ok(format_number(&(n1 / gcd * n2).with_scale(0), ctx))
+}
+
+pub fn round(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    if args.is_empty() || args.len() > 3 {
+        return Err(format!("Expected 1 to 3 arguments, found {}", args.len()));
+    }
+    let number = number_arg(&args[0], ctx)?;
+    let digits = match args.get(1) {
+        Some(digits) => number_arg(digits, ctx)?
+            .to_i64()
+            .filter(|digits| digits.unsigned_abs() <= ctx.config.plain_number_limit as u64)
+            .ok_or_else(|| {
+                format!(
+                    "Invalid digits `{digits}`, expected an integer between -{0} and {0}",
+                    ctx.config.plain_number_limit
+                )
+            })?,
+        None => 0,
+    };
+    let rounding_mode = match args.get(2) {
+        Some(mode) => config::parse_rounding_mode(&mode.to_string()).ok_or_else(|| {
+            format!("Invalid rounding mode `{mode}`, expected `half-up`, `half-even`, `floor`, `ceil` or `toward-zero`")
+        })?,
+        None => ctx.config.rounding_mode,
+    };
+    Ok(format_number(
+        &number.with_scale_round(digits, rounding_mode),
+        ctx,
+    ))
+}
+
+pub fn mode(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [Expression::Array(array)] = args.as_slice() else {
+        return match args.len() {
+            1 => Err(format!("Expected an array found `{}`", args[0])),
+            len => Err(format!("Expected 1 argument, found {len}")),
+        };
+    };
+
+    // The distinct elements in the order they are first seen, with their counts
+    let mut counts: Vec<(&Expression, usize)> = Vec::new();
+    for element in array {
+        let mut seen = None;
+        for (idx, (distinct, _)) in counts.iter().enumerate() {
+            if matches_condition(element, "=", distinct, ctx)? {
+                seen = Some(idx);
+                break;
+            }
+        }
+        match seen {
+            Some(idx) => counts[idx].1 += 1,
+            None => counts.push((element, 1)),
+        }
+    }
+    let mut mode: Option<(&Expression, usize)> = None;
+    for (element, count) in counts {
+        if mode.map_or(true, |(_, mode_count)| count > mode_count) {
+            mode = Some((element, count));
+        }
+    }
+    mode.map(|(element, _)| format_arg(element, ctx))
+        .ok_or_else(|| "Expected a non-empty array".to_owned())
+}
+
+pub fn avg(args: Vec<Expression>, ctx: &mut Context) -> Result<String, BuiltinError> {
+    let numbers = collect_numbers(&args, ctx)?;
+    if numbers.is_empty() {
+        return Err(BuiltinError::division_by_zero(
+            "Division by zero, expected at least one number",
+        ));
+    }
+    let count = BigDecimal::from(numbers.len() as u64);
+    Ok(format_number(
+        &(numbers.into_iter().sum::<BigDecimal>() / count),
+        ctx,
+    ))
+}
+
+pub fn min(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    collect_numbers(&args, ctx)?
+        .into_iter()
+        .min()
+        .map(|min| format_number(&min, ctx))
+        .ok_or_else(|| "min requires at least one value".to_owned())
+}
+
+pub fn max(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    collect_numbers(&args, ctx)?
+        .into_iter()
+        .max()
+        .map(|max| format_number(&max, ctx))
+        .ok_or_else(|| "max requires at least one value".to_owned())
+}
+
+/// The `if` builtin function. The arguments are evaluated before the call, so both branches are
+/// evaluated (and their errors are raised) whatever the condition is.
+pub fn if_(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [condition, then, otherwise] = args.as_slice() else {
+        return Err(format!("Expected 3 arguments, found {}", args.len()));
+    };
+    if condition.to_bool(ctx.config)? {
+        Ok(format_arg(then, ctx))
+    } else {
+        Ok(format_arg(otherwise, ctx))
+    }
+}
+
+pub fn eq(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    compare(&args, "=", ctx)
+}
+
+pub fn gt(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    compare(&args, ">", ctx)
+}
+
+pub fn lt(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    compare(&args, "<", ctx)
+}
+
+pub fn gte(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    compare(&args, ">=", ctx)
+}
+
+pub fn lte(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    compare(&args, "<=", ctx)
+}
+
+/// The `and` builtin function, all the arguments are evaluated and must be booleans.
+pub fn and(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let mut result = true;
+    for arg in &args {
+        result &= arg.to_bool(ctx.config)?;
+    }
+    Ok(result.to_string())
+}
+
+/// The `or` builtin function, all the arguments are evaluated and must be booleans.
+pub fn or(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let mut result = false;
+    for arg in &args {
+        result |= arg.to_bool(ctx.config)?;
+    }
+    Ok(result.to_string())
+}
+
+pub fn not(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [arg] = args.as_slice() else {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    };
+    Ok((!arg.to_bool(ctx.config)?).to_string())
+}
+
+pub fn pi(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    constant(&args, constants::PI, ctx)
+}
+
+pub fn e(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    constant(&args, constants::E, ctx)
+}
+
+pub fn sumproduct(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [Expression::Array(array1), Expression::Array(array2)] = args.as_slice() else {
+        return match args.len() {
+            2 => Err(format!(
+                "Expected 2 arrays, found `{}` and `{}`",
+                args[0], args[1]
+            )),
+            len => Err(format!("Expected 2 arguments, found {len}")),
+        };
+    };
+    if array1.len() != array2.len() {
+        return Err(format!(
+            "Expected 2 arrays of the same length, found {} and {} elements",
+            array1.len(),
+            array2.len()
+        ));
+    }
+    array1
+        .iter()
+        .zip(array2)
+        .try_fold(BigDecimal::zero(), |sum, (element1, element2)| {
+            Ok(sum + number_arg(element1, ctx)? * number_arg(element2, ctx)?)
+        })
+        .map(|sum| format_number(&sum, ctx))
+}
+
+pub fn distinct_count_if(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    if args.len() != 3 {
+        return Err(format!("Expected 3 arguments, found {}", args.len()));
+    }
+    let Expression::Array(array) = &args[0] else {
+        return Err(format!("Expected an array found `{}`", args[0]));
+    };
+    let operator = args[1].to_string();
+
+    let mut distinct: Vec<&Expression> = Vec::new();
+    for element in array {
+        if !matches_condition(element, &operator, &args[2], ctx)? {
+            continue;
+        }
+        let is_new = distinct.iter().try_fold(true, |is_new, seen| {
+            Ok::<_, String>(is_new && !matches_condition(element, "=", seen, ctx)?)
+        })?;
+        if is_new {
+            distinct.push(element);
+        }
+    }
+    Ok(distinct.len().to_string())
+}
+
+pub fn textjoin(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [separator, ignore_empty, values @ ..] = args.as_slice() else {
+        return Err(format!(
+            "Expected at least 2 arguments, found {}",
+            args.len()
+        ));
+    };
+    let ignore_empty = ignore_empty.to_bool(ctx.config)?;
+
+    let texts = flatten_args(values)
+        .into_iter()
+        .map(|value| format_arg(value, ctx))
+        .filter(|text| !(ignore_empty && text.is_empty()))
+        .collect::<Vec<_>>();
+    join_texts(&texts, &separator.to_string(), ctx)
+}
+
+pub fn len(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [arg] = args.as_slice() else {
+        return Err(format!("Expected 1 argument, found {}", args.len()));
+    };
+    Ok(format_arg(arg, ctx).chars().count().to_string())
+}
+
+/// The `substr` (and `mid`) builtin function, a start beyond the end returns an empty string and
+/// a length beyond the end is clamped to it.
+pub fn substr(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [string, start, length] = args.as_slice() else {
+        return Err(format!("Expected 3 arguments, found {}", args.len()));
+    };
+    let start = non_negative_integer(start, "start", ctx)?;
+    let length = non_negative_integer(length, "length", ctx)?;
+    if start == 0 {
+        return Ok(String::new());
+    }
+    Ok(format_arg(string, ctx)
+        .chars()
+        .skip(start - 1)
+        .take(length)
+        .collect())
+}
+
+pub fn concat(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let texts = flatten_args(&args)
+        .into_iter()
+        .map(|value| format_arg(value, ctx))
+        .collect::<Vec<_>>();
+    join_texts(&texts, "", ctx)
+}
+
+pub fn repeat(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [text, count] = args.as_slice() else {
         return Err(format!("Expected 2 arguments, found {}", args.len()));
+    };
+    let text = format_arg(text, ctx);
+    let count = non_negative_integer(count, "count", ctx)?;
+    // Checked before the repeated string is allocated
+    check_string_length(text.chars().count().saturating_mul(count), ctx)?;
+    Ok(text.repeat(count))
+}
+
+/// The `join` builtin function, the nested arrays are flattened.
+pub fn join(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    let [Expression::Array(array), separator] = args.as_slice() else {
+        return match args.len() {
+            2 => Err(format!("Expected an array found `{}`", args[0])),
+            len => Err(format!("Expected 2 arguments, found {len}")),
+        };
+    };
+    let texts = flatten_args(array)
+        .into_iter()
+        .map(|element| format_arg(element, ctx))
+        .collect::<Vec<_>>();
+    join_texts(&texts, &separator.to_string(), ctx)
+}
+
+/// The `concat_if` builtin function, the elements of the first array are tested and the optional values
+/// array has the joined elements, like the `sumif` range and sum range of the spreadsheets.
+pub fn concat_if(args: Vec<Expression>, ctx: &mut Context) -> FunctionResult {
+    if !(4..=5).contains(&args.len()) {
+        return Err(format!("Expected 4 or 5 arguments, found {}", args.len()));
+    }
+    let Expression::Array(array) = &args[0] else {
+        return Err(format!("Expected an array found `{}`", args[0]));
+    };
+    let values = match args.get(4) {
+        Some(Expression::Array(values)) if values.len() == array.len() => values,
+        Some(Expression::Array(values)) => {
+            return Err(format!(
+                "Expected the values array to have {} elements like the tested array, found {}",
+                array.len(),
+                values.len()
+            ))
+        }
+        Some(arg) => return Err(format!("Expected an array found `{arg}`")),
+        None => array,
+    };
+    let operator = args[1].to_string();
+
+    let mut texts = Vec::new();
+    for (element, value) in array.iter().zip(values) {
+        if matches_condition(element, &operator, &args[2], ctx)? {
+            texts.push(format_arg(value, ctx));
+        }
+    }
+    join_texts(&texts, &args[3].to_string(), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bigdecimal::{BigDecimal, RoundingMode, Zero};
+
+    use crate::{config::EngineConfig, engine::Engine};
+
+    /// Evaluates the given formula in the `B1` cell with the given configuration, the `A1` cell is
+    /// the given value.
+    fn eval_with(a1: &str, formula: &str, config: EngineConfig) -> Result<String, String> {
+        let sheet = format!("a,b\n\"{}\",\"{}\"\n", a1, formula.replace('"', "\"\""));
+        Engine::new(PathBuf::from("test.csv"), &sheet, config)
+            .and_then(|mut engine| engine.run_to_string())
+            .map(|output| {
+                let record = output.lines().nth(1).unwrap_or_default();
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(record.as_bytes());
+                reader
+                    .records()
+                    .next()
+                    .and_then(|record| record.ok())
+                    .and_then(|record| record.get(1).map(str::to_owned))
+                    .unwrap_or_default()
+            })
+            .map_err(|err| err.message)
+    }
+
+    /// Evaluates the given formula in the `B1` cell with the default configuration.
+    fn eval(formula: &str) -> Result<String, String> {
+        eval_with("", formula, EngineConfig::default())
+    }
+
+    #[test]
+    fn if_picks_the_branch() {
+        assert_eq!(eval("=if(true;\"y\";\"n\")").unwrap(), "y");
+        assert_eq!(eval("=if(false;\"y\";\"n\")").unwrap(), "n");
+        assert_eq!(eval("=if(eq(1;1);1;2)").unwrap(), "1");
+        assert_eq!(eval("=if(\"true\";1;2)").unwrap(), "1");
+        assert!(eval("=if(1;2;3)").is_err());
+        assert!(eval("=if(true;1)").is_err());
+    }
+
+    #[test]
+    fn if_accepts_nested_booleans_without_boolean_cells() {
+        let config = || EngineConfig {
+            boolean_cells: false,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("", "=if(eq(1;1);\"y\";\"n\")", config()).unwrap(),
+            "y"
+        );
+        assert_eq!(eval_with("", "=if(\"true\";1;2)", config()).unwrap(), "1");
+    }
+
+    #[test]
+    fn logical_builtins_combine_the_booleans() {
+        assert_eq!(eval("=and(true;true;true)").unwrap(), "true");
+        assert_eq!(eval("=and(true;false)").unwrap(), "false");
+        assert_eq!(eval("=or(false;false;true)").unwrap(), "true");
+        assert_eq!(eval("=or(false;false)").unwrap(), "false");
+        assert_eq!(eval("=not(false)").unwrap(), "true");
+        assert!(eval("=not(true;false)").is_err());
+        assert!(eval("=and(1;true)").is_err());
+    }
+
+    #[test]
+    fn logical_builtins_accept_nested_booleans_without_boolean_cells() {
+        let config = || EngineConfig {
+            boolean_cells: false,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("", "=and(true;not(false))", config()).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            eval_with("", "=or(not(true);eq(1;1))", config()).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            eval_with("", "=not(and(true;false))", config()).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            eval_with("", "=if(or(false;true);\"y\";\"n\")", config()).unwrap(),
+            "y"
+        );
+    }
+
+    #[test]
+    fn aggregates_skip_the_error_cells_with_skip_errors() {
+        let config = |skip_errors| EngineConfig {
+            skip_errors,
+            format_profile: crate::config::FormatProfile::Excel,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("=div(1;0)", "=sum(A1;[2;3])", config(true)).unwrap(),
+            "5"
+        );
+        assert_eq!(eval_with("=A9", "=min(A1;4;2)", config(true)).unwrap(), "2");
+        assert_eq!(
+            eval_with("=sum(\"\")", "=avg(A1;4;2)", config(true)).unwrap(),
+            "3"
+        );
+        assert_eq!(
+            eval_with("=div(1;0)", "=sum(A1;2)", config(false)).unwrap(),
+            "#DIV/0!"
+        );
+        // Only the aggregates skip the error cells
+        assert_eq!(
+            eval_with("=div(1;0)", "=mul(A1;2)", config(true)).unwrap(),
+            "#DIV/0!"
+        );
+    }
+
+    #[test]
+    fn skip_errors_keeps_the_error_code_strings() {
+        let config = EngineConfig {
+            skip_errors: true,
+            ..EngineConfig::default()
+        };
+        for code in ["#N/A", "#DIV/0!"] {
+            assert_eq!(
+                eval_with(code, "=sum(A1;2)", config.clone()),
+                Err(format!(
+                    "Builtin function error: Expected a number, found the string `{code}`"
+                )),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn concat_if_joins_the_matching_elements() {
+        assert_eq!(
+            eval("=concat_if([5;12;30];\">\";10;\", \")").unwrap(),
+            "12, 30"
+        );
+        assert_eq!(eval("=concat_if([5;12];\">\";50;\"|\")").unwrap(), "");
+    }
+
+    #[test]
+    fn concat_if_joins_the_values_of_the_matching_rows() {
+        let sheet = "name,age,older\nAli,25,\"=concat_if([B1:B4];\"\">\"\";30;\"\"|\"\";[A1:A4])\"\nSara,41,x\nOmar,30,x\nLina,33,x\n";
+        let output = Engine::new(PathBuf::from("test.csv"), sheet, EngineConfig::default())
+            .and_then(|mut engine| engine.run_to_string())
+            .map_err(|err| err.message)
+            .unwrap();
+        assert_eq!(output.lines().nth(1), Some("Ali,25,Sara|Lina"));
+    }
+
+    #[test]
+    fn concat_if_rejects_the_values_of_another_length() {
+        assert!(eval("=concat_if([1;2];\">\";0;\"|\";[\"a\"])")
+            .unwrap_err()
+            .contains("to have 2 elements"));
+        assert!(eval("=concat_if([1;2];\">\";0;\"|\";\"a\")").is_err());
+    }
+
+    #[test]
+    fn base64_round_trips_the_strings() {
+        for text in ["", "a", "ab", "abc", "héllo, world"] {
+            assert_eq!(
+                eval(&format!("=base64_decode(base64_encode(\"{text}\"))")).unwrap(),
+                text
+            );
+        }
+        assert_eq!(eval("=base64_encode(\"foobar\")").unwrap(), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn base64_decode_rejects_the_invalid_input() {
+        assert!(eval("=base64_decode(\"Zm9\")")
+            .unwrap_err()
+            .contains("Invalid base64 length"));
+        assert!(eval("=base64_decode(\"Zm9*\")")
+            .unwrap_err()
+            .contains("Invalid base64 character"));
+        assert!(eval("=base64_decode(\"/w==\")")
+            .unwrap_err()
+            .contains("not a valid UTF-8 string"));
+    }
+
+    #[test]
+    fn json_extract_accesses_the_nested_values() {
+        let json = r#"{""a"": {""b"": [7, {""c"": ""x""}]}, ""d"": [[1, 2], null]}"#;
+        for (path, value) in [
+            ("a.b[0]", "7"),
+            ("a.b[1].c", "x"),
+            ("d[0][1]", "2"),
+            ("d[1]", "null"),
+            ("a.b[1]", r#"{"c":"x"}"#),
+        ] {
+            assert_eq!(
+                eval_with(
+                    json,
+                    &format!("=json_extract(A1;\"{path}\")"),
+                    EngineConfig::default()
+                )
+                .unwrap(),
+                value,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_extract_rejects_the_invalid_json_and_the_missing_paths() {
+        let json = r#"{""a"": [1]}"#;
+        let extract = |a1: &str, path: &str| {
+            eval_with(
+                a1,
+                &format!("=json_extract(A1;\"{path}\")"),
+                EngineConfig::default(),
+            )
+        };
+        assert!(extract(json, "a[1]")
+            .unwrap_err()
+            .contains("does not exist"));
+        assert!(extract(json, "b").unwrap_err().contains("does not exist"));
+        assert!(extract(json, "a[x]").unwrap_err().contains("invalid index"));
+        assert!(extract(r#"{""a"": }"#, "a").is_err());
+    }
+
+    #[test]
+    fn parses_the_parameter_types_of_every_signature() {
+        for (name, signature, _) in super::BUILTINS {
+            let parameters = super::parameter_types(name).unwrap_or_else(|| panic!("{signature}"));
+            for parameter in parameters {
+                let parameter_type = parameter.trim_end_matches("...").trim_end_matches('?');
+                assert!(
+                    ["any", "number", "string", "boolean", "array"].contains(&parameter_type),
+                    "{signature}"
+                );
+            }
+        }
+        assert_eq!(super::parameter_types("pi"), Some(Vec::new()));
+        assert_eq!(
+            super::parameter_types("round"),
+            Some(vec!["number", "number?", "string?"])
+        );
+    }
+
+    #[test]
+    fn finds_the_mistyped_arguments_from_the_signatures() {
+        use crate::ast::Expression;
+
+        let config = EngineConfig::default();
+        let number = |number: i64| Expression::Number(number.into());
+        let string = |string: &str| Expression::String(string.to_owned());
+        let mistyped =
+            |name: &str, args: Vec<Expression>| super::mistyped_arguments(name, &args, &config);
+
+        assert_eq!(
+            mistyped(
+                "sum",
+                vec![number(1), string("x"), Expression::Boolean(true)]
+            ),
+            [(1, "a number"), (2, "a number")]
+        );
+        assert_eq!(
+            mistyped("sum", vec![Expression::Array(vec![number(1), string("x")])]),
+            [(0, "a number")]
+        );
+        assert_eq!(
+            mistyped(
+                "and",
+                vec![Expression::Boolean(true), string("x"), number(2)]
+            ),
+            [(1, "a boolean"), (2, "a boolean")]
+        );
+        assert_eq!(mistyped("not", vec![string("false")]), []);
+        assert_eq!(
+            mistyped("join", vec![number(1), string(",")]),
+            [(0, "an array")]
+        );
+        assert_eq!(
+            mistyped(
+                "concat_if",
+                vec![
+                    Expression::Array(Vec::new()),
+                    string("="),
+                    number(1),
+                    string(","),
+                    number(2)
+                ]
+            ),
+            [(4, "an array")]
+        );
+        // The any and string parameters, the unknown arguments and functions are not checked
+        assert_eq!(
+            mistyped("concat", vec![Expression::Array(Vec::new()), number(1)]),
+            []
+        );
+        assert_eq!(mistyped("year", vec![number(1)]), []);
+        assert_eq!(
+            mistyped(
+                "sum",
+                vec![Expression::Field {
+                    col: "A".to_owned(),
+                    row: 1,
+                    value: "x".to_owned()
+                }]
+            ),
+            []
+        );
+        assert_eq!(mistyped("vlookup", vec![string("x")]), []);
+
+        let coerced = EngineConfig {
+            coerce_text_numbers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            super::mistyped_arguments("sum", &[string("2")], &coerced),
+            []
+        );
+    }
+
+    #[test]
+    fn signatures_lists_every_builtin() {
+        let signatures = super::signatures();
+        assert_eq!(signatures.lines().count(), super::BUILTINS.len());
+        for (_, signature, description) in super::BUILTINS {
+            assert!(
+                signatures
+                    .lines()
+                    .any(|line| line.starts_with(signature) && line.ends_with(description)),
+                "{signature}"
+            );
+        }
+        for signature in ["sum(number...)", "sub(number; number)", "print(any...)"] {
+            assert!(signatures.contains(signature), "{signature}");
+        }
+    }
+
+    #[test]
+    fn shuffle_is_reproducible_with_a_seed() {
+        let shuffle = |seed| {
+            eval_with(
+                "",
+                "=shuffle([1;2;3;4;5;6;7;8;9;10])",
+                EngineConfig {
+                    seed: Some(seed),
+                    ..EngineConfig::default()
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(shuffle(42), shuffle(42));
+        assert_ne!(shuffle(42), shuffle(43));
+
+        let mut elements = shuffle(42)
+            .trim_matches(['[', ']'])
+            .split(", ")
+            .map(|element| element.parse::<u32>().unwrap())
+            .collect::<Vec<_>>();
+        elements.sort_unstable();
+        assert_eq!(elements, (1..=10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn aggregates_coerce_the_numeric_strings_if_enabled() {
+        let config = || EngineConfig {
+            coerce_text_numbers: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("1", "=sum(A1;[\"2\";3];\"-4.5\")", config()).unwrap(),
+            "1.5"
+        );
+        assert_eq!(
+            eval_with("", "=max([\"10\";2;\"3\"])", config()).unwrap(),
+            "10"
+        );
+        assert!(eval_with("", "=sum([1;\"x\"])", config())
+            .unwrap_err()
+            .contains("x"));
+        assert!(eval("=sum([1;\"2\"])").is_err());
+    }
+
+    #[test]
+    fn collect_numbers_flattens_the_nested_arrays() {
+        use super::{collect_numbers, Context, Expression};
+        use bigdecimal::BigDecimal;
+
+        let number = |n: i32| Expression::Number(BigDecimal::from(n));
+        let config = EngineConfig::default();
+        let ctx = Context {
+            config: &config,
+            rng: &mut crate::rng::Rng::new(0),
+        };
+        let args = [
+            number(1),
+            Expression::Array(vec![number(2), Expression::Array(vec![number(3)])]),
+            Expression::Array(Vec::new()),
+            number(4),
+        ];
+        assert_eq!(
+            collect_numbers(&args, &ctx).unwrap(),
+            (1..=4).map(BigDecimal::from).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn collect_numbers_names_the_bad_element() {
+        use super::{collect_numbers, Context, Expression};
+        use bigdecimal::BigDecimal;
+
+        let config = EngineConfig::default();
+        let ctx = Context {
+            config: &config,
+            rng: &mut crate::rng::Rng::new(0),
+        };
+        let args = [
+            Expression::Number(BigDecimal::from(1)),
+            Expression::Array(vec![Expression::String("oops".to_owned())]),
+        ];
+        let err = collect_numbers(&args, &ctx).unwrap_err();
+        assert!(err.contains("oops"), "{err}");
+        let err = collect_numbers(&[Expression::Boolean(true)], &ctx).unwrap_err();
+        assert!(err.contains("true"), "{err}");
+    }
+
+    #[test]
+    fn string_predicates_check_the_case_sensitively() {
+        let cases = [
+            (r#"=contains("Hello";"ell")"#, "true"),
+            (r#"=contains("Hello";"ELL")"#, "false"),
+            (r#"=icontains("Hello";"ELL")"#, "true"),
+            (r#"=startswith("Hello";"He")"#, "true"),
+            (r#"=startswith("Hello";"he")"#, "false"),
+            (r#"=istartswith("Hello";"hE")"#, "true"),
+            (r#"=endswith("Hello";"lo")"#, "true"),
+            (r#"=endswith("Hello";"LO")"#, "false"),
+            (r#"=iendswith("Hello";"LO")"#, "true"),
+            (r#"=contains("café";"é")"#, "true"),
+            (r#"=iendswith("CAFÉ";"é")"#, "true"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn string_predicates_always_match_the_empty_needle() {
+        for name in [
+            "contains",
+            "icontains",
+            "startswith",
+            "istartswith",
+            "endswith",
+        ] {
+            for haystack in ["Hello", ""] {
+                let formula = format!(r#"={name}("{haystack}";"")"#);
+                assert_eq!(eval(&formula).as_deref(), Ok("true"), "{formula}");
+            }
+        }
+        assert_eq!(eval(r#"=contains("";"a")"#).as_deref(), Ok("false"));
+    }
+
+    #[test]
+    fn pads_the_strings_to_the_width() {
+        assert_eq!(eval(r#"=padleft(7;3;"0")"#).as_deref(), Ok("007"));
+        assert_eq!(eval(r#"=padright("ab";5;".")"#).as_deref(), Ok("ab..."));
+        assert_eq!(eval(r#"=padleft("é";3;"·")"#).as_deref(), Ok("··é"));
+    }
+
+    #[test]
+    fn padding_keeps_the_long_enough_strings() {
+        assert_eq!(eval(r#"=padleft("abc";2;"0")"#).as_deref(), Ok("abc"));
+        assert_eq!(eval(r#"=padright("abc";3;"0")"#).as_deref(), Ok("abc"));
+    }
+
+    #[test]
+    fn padding_rejects_a_non_integer_width() {
+        for (formula, width) in [
+            (r#"=padleft("a";2.5;"0")"#, "2.5"),
+            (r#"=padright("a";-1;"0")"#, "-1"),
+        ] {
+            assert_eq!(
+                eval(formula),
+                Err(format!(
+                    "Builtin function error: Invalid width `{width}`, expected a non-negative integer"
+                )),
+                "{formula}"
+            );
+        }
+        assert_eq!(eval(r#"=padleft("a";0;"0")"#).as_deref(), Ok("a"));
+    }
+
+    #[test]
+    fn repeats_the_strings() {
+        assert_eq!(eval(r#"=repeat("ab";3)"#).as_deref(), Ok("ababab"));
+        assert_eq!(eval("=repeat(1.50;2)").as_deref(), Ok("1.501.50"));
+        assert_eq!(eval(r#"=repeat("ab";0)"#).as_deref(), Ok(""));
+        assert_eq!(
+            eval(r#"=repeat("ab";1.5)"#),
+            Err(
+                "Builtin function error: Invalid count `1.5`, expected a non-negative integer"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn joins_the_array_elements() {
+        assert_eq!(
+            eval(r#"=join(["a";["b"];1];", ")"#).as_deref(),
+            Ok("a, b, 1")
+        );
+        assert_eq!(eval(r#"=join([];"-")"#).as_deref(), Ok(""));
+        assert_eq!(
+            eval(r#"=join("a";"-")"#),
+            Err("Builtin function error: Expected an array found `a`".to_owned())
+        );
+    }
+
+    #[test]
+    fn padding_rejects_a_multi_char_fill() {
+        for formula in [r#"=padleft("a";3;"00")"#, r#"=padright("a";3;"")"#] {
+            assert!(eval(formula)
+                .unwrap_err()
+                .contains("expected one character"));
+        }
+    }
+
+    #[test]
+    fn sumproduct_sums_the_products_of_two_columns() {
+        let config = EngineConfig {
+            delimiter: '|',
+            ..EngineConfig::default()
+        };
+        let output = Engine::new(
+            PathBuf::from("test.csv"),
+            "price|quantity|total\n2|3|=sumproduct([A1:A3];[B1:B3])\n1.5|4|\n10|0|\n",
+            config,
+        )
+        .and_then(|mut engine| engine.run_to_string())
+        .map_err(|err| err.message);
+        assert_eq!(
+            output.as_deref(),
+            Ok("price|quantity|total\n2|3|12.0\n1.5|4|\n10|0|\n")
+        );
+    }
+
+    #[test]
+    fn sumproduct_rejects_the_mismatched_arrays() {
+        assert_eq!(
+            eval("=sumproduct([1;2;3];[4;5])"),
+            Err(
+                "Builtin function error: Expected 2 arrays of the same length, found 3 and 2 elements"
+                    .to_owned()
+            )
+        );
+        assert!(eval(r#"=sumproduct([1;"x"];[4;5])"#)
+            .unwrap_err()
+            .contains('x'));
+    }
+
+    #[test]
+    fn distinct_count_if_counts_the_unique_matching_elements() {
+        let output = Engine::new(
+            PathBuf::from("test.csv"),
+            "n,count\n3,=distinct_count_if([A1:A6];\">\";2)\n1,\n3,\n5,\n2,\n5,\n",
+            EngineConfig::default(),
+        )
+        .and_then(|mut engine| engine.run_to_string())
+        .map_err(|err| err.message);
+        assert_eq!(output.as_deref(), Ok("n,count\n3,2\n1,\n3,\n5,\n2,\n5,\n"));
+        assert_eq!(
+            eval(r#"=distinct_count_if(["a";"b";"a";"c";"b"];"<>";"c")"#).as_deref(),
+            Ok("2")
+        );
+        assert_eq!(
+            eval(r#"=distinct_count_if([1;1;2];">";5)"#).as_deref(),
+            Ok("0")
+        );
+    }
+
+    #[test]
+    fn textjoin_joins_a_range_with_and_without_the_empties() {
+        let join = |ignore_empty| {
+            let formula = format!("=textjoin(\"-\";{ignore_empty};A1:A4)");
+            let sheet = format!("name,joined\na,{formula}\n,\nb,\n,\n");
+            Engine::new(PathBuf::from("test.csv"), &sheet, EngineConfig::default())
+                .and_then(|mut engine| engine.run_to_string())
+                .map(|output| output.lines().nth(1).unwrap_or_default().to_owned())
+                .map_err(|err| err.message)
+        };
+        assert_eq!(join(true).as_deref(), Ok("a,a-b"));
+        assert_eq!(join(false).as_deref(), Ok("a,a--b-"));
+        assert_eq!(
+            eval(r#"=textjoin(", ";true;"x";["";"y"])"#).as_deref(),
+            Ok("x, y")
+        );
+    }
+
+    #[test]
+    fn registry_dispatches_every_builtin() {
+        let config = EngineConfig::default();
+        let mut rng = crate::rng::Rng::new(0);
+        let mut ctx = super::Context {
+            config: &config,
+            rng: &mut rng,
+        };
+        // `iferror` is evaluated by the engine, it catches the errors of its argument
+        let dispatched = super::BUILTINS
+            .iter()
+            .map(|(name, _, _)| *name)
+            .filter(|name| name != &"iferror")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            super::REGISTRY.with(std::collections::HashMap::len),
+            dispatched.len()
+        );
+        for name in dispatched {
+            assert!(
+                super::call_builtin(name, Vec::new(), &mut ctx).is_some(),
+                "{name}"
+            );
+        }
+        assert!(super::call_builtin("vlookup", Vec::new(), &mut ctx).is_none());
+    }
+
+    /// The builtin function of the synthetic builtin names.
+    fn synthetic_builtin(
+        args: Vec<crate::ast::Expression>,
+        ctx: &mut super::Context,
+    ) -> super::BuiltinResult {
+        super::print(args, ctx).map(Into::into).map_err(Into::into)
+    }
+
+    /// Dispatches the given name with a `match` over the synthetic builtin names, each prefix
+    /// followed by `_` and each suffix, the arms are accumulated one prefix at a time.
+    macro_rules! synthetic_match {
+        ($name: expr, [$($arms: tt)*], [], [$($suffix: ident)*]) => {
+            match $name {
+                $($arms)*
+                _ => None,
+            }
+        };
+        (
+            $name: expr,
+            [$($arms: tt)*],
+            [$prefix: ident $($prefixes: ident)*],
+            [$($suffix: ident)*]
+        ) => {
+            synthetic_match!(
+                $name,
+                [
+                    $($arms)*
+                    $(
+                        concat!(stringify!($prefix), "_", stringify!($suffix)) =>
+                            Some(synthetic_builtin as super::BuiltinFunction),
+                    )*
+                ],
+                [$($prefixes)*],
+                [$($suffix)*]
+            )
+        };
+    }
+
+    /// The synthetic builtin names of [`synthetic_match`], the 4096 `p{0..64}_s{0..64}` names.
+    fn synthetic_match(name: &str) -> Option<super::BuiltinFunction> {
+        synthetic_match!(
+            name,
+            [],
+            [
+                p0 p1 p2 p3 p4 p5 p6 p7 p8 p9 p10 p11 p12 p13 p14 p15 p16 p17 p18 p19 p20 p21 p22
+                p23 p24 p25 p26 p27 p28 p29 p30 p31 p32 p33 p34 p35 p36 p37 p38 p39 p40 p41 p42
+                p43 p44 p45 p46 p47 p48 p49 p50 p51 p52 p53 p54 p55 p56 p57 p58 p59 p60 p61 p62
+                p63
+            ],
+            [
+                s0 s1 s2 s3 s4 s5 s6 s7 s8 s9 s10 s11 s12 s13 s14 s15 s16 s17 s18 s19 s20 s21 s22
+                s23 s24 s25 s26 s27 s28 s29 s30 s31 s32 s33 s34 s35 s36 s37 s38 s39 s40 s41 s42
+                s43 s44 s45 s46 s47 s48 s49 s50 s51 s52 s53 s54 s55 s56 s57 s58 s59 s60 s61 s62
+                s63
+            ]
+        )
+    }
+
+    #[test]
+    #[ignore = "a timing comparison, run it with `cargo test --release -- --ignored --nocapture`"]
+    fn compares_the_map_and_the_match_dispatch_timings() {
+        let registry = (0..64)
+            .flat_map(|prefix| (0..64).map(move |suffix| format!("p{prefix}_s{suffix}")))
+            .map(|name| (name, synthetic_builtin as super::BuiltinFunction))
+            .collect::<std::collections::HashMap<_, _>>();
+        // A million lookups of the synthetic names, a fifth of them are unknown
+        let names = (0..1_000_000)
+            .map(|i| format!("p{}_s{}", i % 64, i / 64 % 80))
+            .collect::<Vec<_>>();
+        let time = |dispatch: &dyn Fn(&str) -> Option<super::BuiltinFunction>| {
+            let start = std::time::Instant::now();
+            let found = names.iter().filter(|name| dispatch(name).is_some()).count();
+            (found, start.elapsed())
+        };
+
+        let (map_found, map) = time(&|name| registry.get(name).copied());
+        let (match_found, matched) = time(&synthetic_match);
+        assert_eq!(map_found, match_found);
+        println!(
+            "{} builtins, {} lookups: map {map:?}, match {matched:?}",
+            registry.len(),
+            names.len()
+        );
+    }
+
+    #[test]
+    fn registry_dispatches_to_the_named_functions() {
+        let cases = [
+            (r#"=if(true;"a";"b")"#, "a"),
+            (r#"=mid("hello";2;3)"#, "ell"),
+            (r#"=substr("hello";2;3)"#, "ell"),
+            ("=sum(1;2)", "3"),
+            ("=sub(1;2)", "-1"),
+            ("=mul(2;3)", "6"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn coerces_the_booleans_with_coerce_bool() {
+        let config = || EngineConfig {
+            coerce_bool: true,
+            ..EngineConfig::default()
+        };
+        let cases = [
+            (r#"=if(1;"y";"n")"#, "y"),
+            (r#"=if(0;"y";"n")"#, "n"),
+            (r#"=if("yes";"y";"n")"#, "y"),
+            (r#"=and("no";true)"#, "false"),
+            ("=or(0;1)", "true"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(
+                eval_with("", formula, config()).as_deref(),
+                Ok(expected),
+                "{formula}"
+            );
+        }
+        assert!(eval_with("", r#"=if(2;"y";"n")"#, config()).is_err());
+    }
+
+    #[test]
+    fn rejects_the_non_booleans_without_coerce_bool() {
+        for formula in [r#"=if(1;"y";"n")"#, r#"=and("yes";true)"#, "=not(0)"] {
+            assert!(
+                eval(formula).unwrap_err().contains("Expected a boolean"),
+                "{formula}"
+            );
+        }
+    }
+
+    #[test]
+    fn gcd_and_lcm_of_the_integers() {
+        let cases = [
+            ("=gcd(12;18)", "6"),
+            ("=lcm(4;6)", "12"),
+            ("=gcd(-12;18)", "6"),
+            ("=gcd(0;5)", "5"),
+            ("=lcm(0;5)", "0"),
+            ("=gcd(12.0;18)", "6"),
+            ("=lcm(21;6)", "42"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn gcd_and_lcm_reject_the_non_integers() {
+        for formula in ["=gcd(1.5;3)", "=lcm(4;0.5)"] {
+            assert!(
+                eval(formula)
+                    .unwrap_err()
+                    .contains("Expected an integer, found"),
+                "{formula}"
+            );
+        }
+    }
+
+    #[test]
+    fn suggests_the_closest_builtin_of_an_unknown_function() {
+        assert_eq!(super::closest_builtin("prnit"), Some("print"));
+        assert_eq!(super::closest_builtin("SUM"), None);
+        assert_eq!(super::closest_builtin("vlookup"), None);
+        assert_eq!(
+            eval(r#"=prnit("x")"#),
+            Err("Unknown function prnit, did you mean print?".to_owned())
+        );
     }
-    match (&args[0], &args[1]) {
-        (Expression::Number(n1), Expression::Number(n2)) => Ok((n1 / n2).to_string()),
-        (a1, a2) => Err(format!("Expected numbers found `{a2}` and `{a1}`")),
+
+    #[test]
+    fn round_with_the_rounding_modes() {
+        let round = |formula: &str, rounding_mode| {
+            let config = EngineConfig {
+                rounding_mode,
+                ..EngineConfig::default()
+            };
+            eval_with("", formula, config)
+        };
+        let (half_up, half_even) = (RoundingMode::HalfUp, RoundingMode::HalfEven);
+        assert_eq!(round("=round(2.5)", half_up).as_deref(), Ok("3"));
+        assert_eq!(round("=round(2.5)", half_even).as_deref(), Ok("2"));
+        assert_eq!(round("=round(3.5)", half_up).as_deref(), Ok("4"));
+        assert_eq!(round("=round(3.5)", half_even).as_deref(), Ok("4"));
+        assert_eq!(round("=round(-2.5)", half_up).as_deref(), Ok("-3"));
+        assert_eq!(round("=round(1.25;1)", half_even).as_deref(), Ok("1.2"));
+
+        let cases = [
+            ("=round(2.7;0;\"floor\")", "2"),
+            ("=round(-2.7;0;\"floor\")", "-3"),
+            ("=round(2.1;0;\"ceil\")", "3"),
+            ("=round(-2.7;0;\"toward-zero\")", "-2"),
+            ("=round(2.5;0;\"half-even\")", "2"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(
+                round(formula, half_up).as_deref(),
+                Ok(expected),
+                "{formula}"
+            );
+        }
+        assert!(round("=round(2.5;0;\"up\")", half_up)
+            .unwrap_err()
+            .contains("Invalid rounding mode `up`"));
+    }
+
+    #[test]
+    fn mode_returns_the_most_frequent_element() {
+        let output = Engine::new(
+            PathBuf::from("test.csv"),
+            "n,mode\n3,=mode([A1:A5])\n1,\n1,\n2,\n1,\n",
+            EngineConfig::default(),
+        )
+        .and_then(|mut engine| engine.run_to_string())
+        .map_err(|err| err.message);
+        assert_eq!(output.as_deref(), Ok("n,mode\n3,1\n1,\n1,\n2,\n1,\n"));
+        assert_eq!(eval(r#"=mode(["b";"a";"a";"c"])"#).as_deref(), Ok("a"));
+        assert_eq!(eval("=mode([1;2.0;2])").as_deref(), Ok("2.0"));
+    }
+
+    #[test]
+    fn mode_breaks_the_ties_with_the_first_seen_element() {
+        assert_eq!(eval(r#"=mode([3;"a";"a";3;1])"#).as_deref(), Ok("3"));
+        assert_eq!(eval("=mode([2;1;1;2])").as_deref(), Ok("2"));
+        // An all-unique array returns its first element
+        assert_eq!(eval(r#"=mode([5;"x";1])"#).as_deref(), Ok("5"));
+        assert_eq!(
+            eval("=mode([])"),
+            Err("Builtin function error: Expected a non-empty array".to_owned())
+        );
+    }
+
+    #[test]
+    fn limits_the_length_of_the_produced_strings() {
+        let config = || EngineConfig {
+            max_string_length: 5,
+            ..EngineConfig::default()
+        };
+        let oversized = [
+            (r#"=padleft("a";6;"-")"#, 6),
+            (r#"=padright("a";1000000000;"-")"#, 1000000000),
+            (r#"=concat("abc";"def")"#, 6),
+            (r#"=textjoin("--";true;["ab";"cd"])"#, 6),
+            (r#"=repeat("ab";3)"#, 6),
+            (r#"=repeat("ab";1000000000)"#, 2000000000),
+            (r#"=join(["ab";"cd"];"--")"#, 6),
+            (r#"=join([1;[2;3]];"--")"#, 7),
+        ];
+        for (formula, length) in oversized {
+            assert_eq!(
+                eval_with("", formula, config()),
+                Err(format!(
+                    "Builtin function error: The result is {length} characters long, \
+                     the maximum string length is 5"
+                )),
+                "{formula}"
+            );
+        }
+        assert_eq!(
+            eval_with("", r#"=textjoin("-";true;["ab";"cd"])"#, config()).as_deref(),
+            Ok("ab-cd")
+        );
+    }
+
+    #[test]
+    fn avg_averages_the_numbers_and_the_arrays() {
+        assert_eq!(eval("=avg(1;2;3)").as_deref(), Ok("2"));
+        assert_eq!(eval("=avg([1;2;3];4)").as_deref(), Ok("2.5"));
+        assert_eq!(eval("=avg([];5)").as_deref(), Ok("5"));
+    }
+
+    #[test]
+    fn avg_rejects_the_empty_and_non_numeric_arguments() {
+        for formula in ["=avg()", "=avg([])"] {
+            assert_eq!(
+                eval(formula),
+                Err(
+                    "Builtin function error: Division by zero, expected at least one number"
+                        .to_owned()
+                ),
+                "{formula}"
+            );
+        }
+        assert!(eval(r#"=avg(1;"x")"#).unwrap_err().contains('x'));
+    }
+
+    #[test]
+    fn min_and_max_of_the_numbers_and_the_nested_arrays() {
+        let cases = [
+            ("=min(3;-2;5)", "-2"),
+            ("=max(3;-2;5)", "5"),
+            ("=min([4;[-7.5;2]];0)", "-7.5"),
+            ("=max([4;[-7.5;[12]]];0)", "12"),
+            ("=max(-3;-1.0)", "-1.0"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn min_and_max_reject_the_empty_and_non_numeric_arguments() {
+        for name in ["min", "max"] {
+            for args in ["", "[]", "[[]]"] {
+                assert_eq!(
+                    eval(&format!("={name}({args})")),
+                    Err(format!(
+                        "Builtin function error: {name} requires at least one value"
+                    ))
+                );
+            }
+            let err = eval(&format!(r#"={name}(1;[2;"oops"])"#)).unwrap_err();
+            assert!(err.contains("oops"), "{err}");
+        }
+    }
+
+    #[test]
+    fn comparisons_compare_the_numbers_and_the_strings() {
+        let cases = [
+            ("=eq(1.0;1)", "true"),
+            (r#"=eq("a";"a")"#, "true"),
+            (r#"=eq("a";"b")"#, "false"),
+            (r#"=eq("1.0";"1")"#, "false"),
+            ("=gt(2;1)", "true"),
+            ("=lt(2;1)", "false"),
+            ("=gte(1;1.00)", "true"),
+            ("=lte(-1;-2)", "false"),
+            (r#"=if(eq(1;1.0);"same";"different")"#, "same"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn comparisons_reject_the_non_numeric_orderings() {
+        assert!(eval(r#"=gt("a";"b")"#)
+            .unwrap_err()
+            .contains("The `>` operator compares numbers"));
+        for name in ["eq", "gt", "lt", "gte", "lte"] {
+            assert_eq!(
+                eval(&format!("={name}(1)")),
+                Err("Builtin function error: Expected 2 arguments, found 1".to_owned()),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn constants_start_with_their_digits() {
+        let pi = eval("=pi()").unwrap();
+        let e = eval("=e()").unwrap();
+        assert!(pi.starts_with("3.14159265358979323846"), "{pi}");
+        assert!(e.starts_with("2.71828182845904523536"), "{e}");
+        // 100 fraction digits by default
+        assert_eq!((pi.len(), e.len()), (102, 102));
+        assert!(eval("=pi(1)").is_err());
+    }
+
+    #[test]
+    fn constants_are_rounded_to_the_constants_precision() {
+        let precision = |constants_precision| EngineConfig {
+            constants_precision,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("", "=pi()", precision(4)).as_deref(),
+            Ok("3.1416")
+        );
+        assert_eq!(eval_with("", "=e()", precision(4)).as_deref(), Ok("2.7183"));
+        assert_eq!(eval_with("", "=pi()", precision(0)).as_deref(), Ok("3"));
+        let pi = eval_with("", "=pi()", precision(1000)).unwrap();
+        assert_eq!(pi.len(), 1002);
+        assert!(pi.starts_with(&eval("=pi()").unwrap()[..98]));
+    }
+
+    #[test]
+    fn deterministic_requires_a_seed_for_the_nondeterministic_builtins() {
+        let config = |seed| EngineConfig {
+            deterministic: true,
+            seed,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("", "=shuffle([1;2;3])", config(None)),
+            Err(
+                "Builtin function error: The shuffle function is nondeterministic, it requires \
+                 a seed (`--seed`) with `--deterministic`"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            eval_with("", "=shuffle([1;2;3])", config(Some(0))),
+            eval_with("", "=shuffle([1;2;3])", config(Some(0)))
+        );
+        assert!(eval_with("", "=shuffle([1;2;3])", config(Some(0))).is_ok());
+        assert_eq!(eval_with("", "=sum(1;2)", config(None)).as_deref(), Ok("3"));
+    }
+
+    #[test]
+    fn rand_errors_under_deterministic_without_a_seed() {
+        let config = EngineConfig {
+            deterministic: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            eval_with("", "=rand()", config),
+            Err(
+                "Builtin function error: The rand function is nondeterministic, it requires \
+                 a seed (`--seed`) with `--deterministic`"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn rand_is_reproducible_with_a_seed() {
+        let rand = |seed| {
+            let config = EngineConfig {
+                deterministic: true,
+                seed: Some(seed),
+                ..EngineConfig::default()
+            };
+            eval_with("", "=rand()", config).unwrap()
+        };
+        assert_eq!(rand(42), rand(42));
+        assert_ne!(rand(42), rand(43));
+        for seed in 0..20 {
+            let number = rand(seed).parse::<BigDecimal>().unwrap();
+            assert!(
+                number >= BigDecimal::zero() && number < BigDecimal::from(1),
+                "{number}"
+            );
+        }
+        assert!(eval("=rand(1)").is_err());
+    }
+
+    #[test]
+    fn concat_joins_the_arguments_without_separators() {
+        let cases = [
+            (r#"=concat(["a";"b"];"c")"#, "abc"),
+            (r#"=concat("x";1.50;-2;true)"#, "x1.50-2true"),
+            (r#"=concat(["a";[1;"b"]];"")"#, "a1b"),
+            ("=concat()", ""),
+            ("=concat(0.10;1E+2)", "0.10100"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn len_counts_the_unicode_scalar_values() {
+        let cases = [
+            (r#"=len("héllo")"#, "5"),
+            ("=len(12345)", "5"),
+            ("=len(1.50)", "4"),
+            (r#"=len("")"#, "0"),
+            (r#"=len("日本")"#, "2"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+        assert_eq!(
+            eval_with("héllo", "=len(A1)", EngineConfig::default()).as_deref(),
+            Ok("5")
+        );
+        for formula in ["=len()", r#"=len("a";"b")"#] {
+            assert!(eval(formula).is_err(), "{formula}");
+        }
+    }
+
+    #[test]
+    fn substr_extracts_the_characters() {
+        let cases = [
+            (r#"=substr("hello";2;3)"#, "ell"),
+            (r#"=mid("héllo";2;10)"#, "éllo"),
+            (r#"=substr("hello";1;0)"#, ""),
+            (r#"=substr("hello";6;2)"#, ""),
+            (r#"=substr("hello";100;2)"#, ""),
+            (r#"=substr("hello";0;2)"#, ""),
+            ("=substr(12345;2;2)", "23"),
+        ];
+        for (formula, expected) in cases {
+            assert_eq!(eval(formula).as_deref(), Ok(expected), "{formula}");
+        }
+    }
+
+    #[test]
+    fn substr_rejects_the_invalid_arguments() {
+        for formula in [
+            r#"=substr("hello";-1;2)"#,
+            r#"=substr("hello";1.5;2)"#,
+            r#"=substr("hello";1;-2)"#,
+            r#"=substr("hello";1)"#,
+        ] {
+            assert!(eval(formula).is_err(), "{formula}");
+        }
     }
 }
@@ -0,0 +1,152 @@
+//! The on-disk evaluation cache of the formulas, persisted across the runs as a JSON object.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::{config::EngineConfig, digest, json::JsonValue};
+
+/// The formulas evaluation cache, the keys are the SHA-256 of the formula, its referenced values and the
+/// options that change the results.
+#[derive(Debug, Default)]
+pub struct EvaluationCache {
+    entries: HashMap<String, String>,
+}
+
+impl EvaluationCache {
+    /// Loads the cache from the given file, a missing file is an empty cache.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read the cache file {}: {err}", path.display()))?;
+        let JsonValue::Object(object) = JsonValue::parse(&content)? else {
+            return Err(format!(
+                "Invalid cache file {}, expected a JSON object",
+                path.display()
+            ));
+        };
+        let entries = object
+            .into_iter()
+            .map(|(key, value)| match value {
+                JsonValue::String(value) => Ok((key, value)),
+                value => Err(format!(
+                    "Invalid cache file {}, expected a string value, found `{value}`",
+                    path.display()
+                )),
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { entries })
+    }
+
+    /// Saves the cache to the given file, the keys are sorted to keep the file stable.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(key, value)| (key.clone(), JsonValue::String(value.clone())))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+        std::fs::write(path, JsonValue::Object(entries).to_string())
+            .map_err(|err| format!("Cannot write the cache file {}: {err}", path.display()))
+    }
+
+    /// Returns the key of the given formula and the values of its referenced cells, evaluated with the
+    /// given configuration.
+    pub fn key(formula: &str, referenced_values: &[String], config: &EngineConfig) -> String {
+        let mut material = config.results_fingerprint();
+        material.push('\0');
+        material.push_str(formula);
+        for value in referenced_values {
+            material.push('\0');
+            material.push_str(value);
+        }
+        digest::to_hex(&digest::sha256(material.as_bytes()))
+    }
+
+    /// Returns the cached value of the given key.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.entries.get(key)
+    }
+
+    /// Caches the value of the given key.
+    pub fn insert(&mut self, key: String, value: String) {
+        self.entries.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::RoundingMode;
+
+    use super::*;
+
+    #[test]
+    fn key_depends_on_the_formula_and_the_referenced_values() {
+        let config = EngineConfig::default();
+        let key = EvaluationCache::key("=sum(A1;1)", &["1".to_owned()], &config);
+        assert_eq!(
+            key,
+            EvaluationCache::key("=sum(A1;1)", &["1".to_owned()], &config)
+        );
+        assert_ne!(
+            key,
+            EvaluationCache::key("=sum(A1;2)", &["1".to_owned()], &config)
+        );
+        assert_ne!(
+            key,
+            EvaluationCache::key("=sum(A1;1)", &["2".to_owned()], &config)
+        );
+    }
+
+    #[test]
+    fn key_depends_on_the_results_options() {
+        let config = EngineConfig::default();
+        let key = EvaluationCache::key("=round(2.5)", &[], &config);
+        let configs = [
+            EngineConfig {
+                rounding_mode: RoundingMode::HalfEven,
+                ..EngineConfig::default()
+            },
+            EngineConfig {
+                coerce_text_numbers: true,
+                ..EngineConfig::default()
+            },
+            EngineConfig {
+                boolean_cells: false,
+                ..EngineConfig::default()
+            },
+            EngineConfig {
+                plain_number_limit: 3,
+                ..EngineConfig::default()
+            },
+            EngineConfig {
+                constants_precision: 5,
+                ..EngineConfig::default()
+            },
+        ];
+        for config in configs {
+            assert_ne!(key, EvaluationCache::key("=round(2.5)", &[], &config));
+        }
+        let config = EngineConfig {
+            progress: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(key, EvaluationCache::key("=round(2.5)", &[], &config));
+    }
+
+    #[test]
+    fn saves_and_loads_the_entries() {
+        let path =
+            std::env::temp_dir().join(format!("minicel-cache-test-{}.json", std::process::id()));
+        let mut cache = EvaluationCache::default();
+        cache.insert("key".to_owned(), "value \"quoted\"".to_owned());
+        cache.save(&path).unwrap();
+        let loaded = EvaluationCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded.get("key").map(String::as_str),
+            Some("value \"quoted\"")
+        );
+        assert!(EvaluationCache::load(&path).unwrap().get("key").is_none());
+    }
+}
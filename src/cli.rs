@@ -0,0 +1,573 @@
+//! The command line interface of the minicel-rs.
+
+use std::{ops::RangeInclusive, path::PathBuf};
+
+use bigdecimal::Signed;
+use is_terminal::IsTerminal;
+
+use minicel_rs::{
+    config::{self, EngineConfig, FormatProfile, NewlineStyle, OtherRows},
+    encoding::{Encoding, UnmappableChars},
+    utils,
+};
+
+/// The action requested by the command line.
+#[derive(Debug, Default)]
+pub enum Action {
+    /// Run the engine on the input file and write the result to the output file.
+    Run {
+        /// The input CSV file
+        input: PathBuf,
+        /// The output CSV file
+        output: PathBuf,
+    },
+    /// Run the engine on the inline CSV data and write the result to the output file, or stdout.
+    RunData {
+        /// The CSV content, the `\n` sequences are newlines
+        data: String,
+        /// The output CSV file, `None` writes to stdout
+        output: Option<PathBuf>,
+    },
+    /// Print the inferred type of each column of the input file and exit.
+    DetectTypes {
+        /// The input CSV file
+        input: PathBuf,
+    },
+    /// Print the cells dependency graph of the input file in the GraphViz DOT format and exit.
+    DumpDepsDot {
+        /// The input CSV file
+        input: PathBuf,
+    },
+    /// Print the builtin functions and exit.
+    #[default]
+    ListBuiltins,
+    /// Evaluate an example formula of each builtin function and exit. (Hidden)
+    SelfTest,
+}
+
+/// The input file formats.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// A CSV sheet
+    #[default]
+    Csv,
+    /// A JSON array of objects, the header is the union of the objects keys
+    Json,
+}
+
+/// The parsed command line arguments.
+#[derive(Debug, Default)]
+pub struct Cli {
+    /// The requested action
+    pub action: Action,
+    /// The engine configuration built from the options
+    pub config: EngineConfig,
+    /// The format of the input file
+    pub input_format: InputFormat,
+    /// The input path is a glob pattern of the input files to concatenate
+    pub input_glob: bool,
+    /// Print the SHA-256 checksum of the output file to stderr
+    pub checksum: bool,
+    /// Report the columns that are not referenced by any formula to stderr
+    pub report_unused_columns: bool,
+    /// The assertions of the evaluated cells, checked after the run
+    pub assertions: Vec<Assertion>,
+    /// Print a remediation hint after the evaluation error
+    pub explain_errors: bool,
+}
+
+/// An assertion of an evaluated cell value. e.g. `C3==42`
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    /// The asserted cell, as written. e.g. `C3`
+    pub cell: String,
+    /// The column of the cell (Starting from 0)
+    pub col: usize,
+    /// The row of the cell (Starting from 1)
+    pub row: u64,
+    /// The expected value of the cell
+    pub expected: String,
+}
+
+impl Assertion {
+    /// Parses the given `CELL==value` assertion.
+    fn parse(assertion: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!("Invalid `--assert` assertion `{assertion}`, expected `CELL==value` (e.g. `C3==42`)")
+        };
+        let (cell, expected) = assertion.split_once("==").ok_or_else(invalid)?;
+        let cell = cell.trim();
+        let (col, row) = utils::parse_cell_reference(cell).ok_or_else(invalid)?;
+        Ok(Self {
+            cell: cell.to_owned(),
+            col,
+            row,
+            expected: expected.to_owned(),
+        })
+    }
+}
+
+/// Parses the given rows ranges, e.g. `2-5,8`. The rows start from 1.
+fn parse_row_ranges(ranges: &str) -> Result<Vec<RangeInclusive<usize>>, String> {
+    ranges
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            let invalid = || {
+                format!("Invalid `--only-rows` range `{range}`, expected a row or a rows range (e.g. `2-5,8`)")
+            };
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = start.trim().parse::<usize>().map_err(|_| invalid())?;
+            let end = end.trim().parse::<usize>().map_err(|_| invalid())?;
+            if start == 0 || start > end {
+                return Err(invalid());
+            }
+            Ok(start..=end)
+        })
+        .collect()
+}
+
+/// Returns the value of the given option, the value is the next argument.
+fn option_value(name: &str, args: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("The option `{name}` requires a value"))
+}
+
+impl Cli {
+    /// Parses the given command line arguments, without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut config = EngineConfig::default();
+        let mut paths = Vec::new();
+        let mut list_builtins = false;
+        let mut self_test = false;
+        let mut detect_types = false;
+        let mut dump_deps_dot = false;
+        let mut cli = Self::default();
+        let mut data = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--encoding-out" => {
+                    let label = option_value(&arg, &mut args)?;
+                    config.output_encoding = Encoding::from_label(&label)
+                        .ok_or_else(|| format!("Unknown encoding `{label}`"))?;
+                }
+                "--encoding-errors" => {
+                    config.unmappable_chars = match option_value(&arg, &mut args)?.as_str() {
+                        "error" => UnmappableChars::Error,
+                        "replace" => UnmappableChars::Replace,
+                        value => {
+                            return Err(format!(
+                                "Invalid `--encoding-errors` value `{value}`, expected `error` or `replace`"
+                            ))
+                        }
+                    };
+                }
+                "--normalize-newlines" => {
+                    config.newline_style = match option_value(&arg, &mut args)?.as_str() {
+                        "lf" => Some(NewlineStyle::Lf),
+                        "crlf" => Some(NewlineStyle::CrLf),
+                        value => {
+                            return Err(format!(
+                                "Invalid `--normalize-newlines` value `{value}`, expected `lf` or `crlf`"
+                            ))
+                        }
+                    };
+                }
+                "--only-formulas" => config.only_formulas = true,
+                "--seed" => {
+                    let seed = option_value(&arg, &mut args)?;
+                    config.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("Invalid `--seed` value `{seed}`"))?,
+                    );
+                }
+                "--max-string-length" => {
+                    let length = option_value(&arg, &mut args)?;
+                    config.max_string_length = length
+                        .parse()
+                        .map_err(|_| format!("Invalid `--max-string-length` value `{length}`"))?;
+                }
+                "--max-line-length" => {
+                    let length = option_value(&arg, &mut args)?;
+                    config.max_line_length = length
+                        .parse()
+                        .map_err(|_| format!("Invalid `--max-line-length` value `{length}`"))?;
+                }
+                "--max-output-size" => {
+                    let size = option_value(&arg, &mut args)?;
+                    config.max_output_size = size
+                        .parse()
+                        .map_err(|_| format!("Invalid `--max-output-size` value `{size}`"))?;
+                }
+                "--flush-interval" => {
+                    let interval = option_value(&arg, &mut args)?;
+                    config.flush_interval = interval
+                        .parse()
+                        .map_err(|_| format!("Invalid `--flush-interval` value `{interval}`"))?;
+                }
+                "--max-arguments" => {
+                    let max_arguments = option_value(&arg, &mut args)?;
+                    config.max_arguments = max_arguments.parse().map_err(|_| {
+                        format!("Invalid `--max-arguments` value `{max_arguments}`")
+                    })?;
+                }
+                "--constants-precision" => {
+                    let precision = option_value(&arg, &mut args)?;
+                    config.constants_precision = precision
+                        .parse()
+                        .ok()
+                        .filter(|precision| *precision <= config::MAX_CONSTANTS_PRECISION)
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid `--constants-precision` value `{precision}`, expected at most {} digits",
+                                config::MAX_CONSTANTS_PRECISION
+                            )
+                        })?;
+                }
+                "--max-iterations" => {
+                    let iterations = option_value(&arg, &mut args)?;
+                    config.max_iterations = iterations
+                        .parse()
+                        .map_err(|_| format!("Invalid `--max-iterations` value `{iterations}`"))?;
+                }
+                "--iteration-tolerance" => {
+                    let tolerance = option_value(&arg, &mut args)?;
+                    config.iteration_tolerance = utils::parse_number(&tolerance)
+                        .filter(|tolerance| !tolerance.is_negative())
+                        .ok_or_else(|| {
+                            format!("Invalid `--iteration-tolerance` value `{tolerance}`")
+                        })?;
+                }
+                "--plain-number-limit" => {
+                    let limit = option_value(&arg, &mut args)?;
+                    config.plain_number_limit = limit
+                        .parse()
+                        .map_err(|_| format!("Invalid `--plain-number-limit` value `{limit}`"))?;
+                }
+                "--dedupe-columns" => config.dedupe_columns = true,
+                "--no-boolean-cells" => config.boolean_cells = false,
+                "--allow-unknown-functions" => config.allow_unknown_functions = true,
+                "--coerce-bool" => config.coerce_bool = true,
+                "--allow-self-reference" => config.allow_self_reference = true,
+                "--strip-formulas" => config.strip_formulas = true,
+                "--deterministic" => config.deterministic = true,
+                "--coerce-text-numbers" => config.coerce_text_numbers = true,
+                "--decimal-comma" => config.decimal_comma = true,
+                "--skip-errors" => config.skip_errors = true,
+                "--keep-going-rows" => config.keep_going_rows = true,
+                "--tee" => config.tee = true,
+                "--arg-separator" => {
+                    let separator = option_value(&arg, &mut args)?;
+                    let mut chars = separator.chars();
+                    config.arg_separator = match (chars.next(), chars.next()) {
+                        (Some(c), None)
+                            if !c.is_alphanumeric()
+                                && !c.is_whitespace()
+                                && !"()[]\"_-.=:".contains(c) =>
+                        {
+                            c
+                        }
+                        _ => return Err(format!("Invalid argument separator `{separator}`")),
+                    };
+                }
+                "--header-prefix" => {
+                    let prefix = option_value(&arg, &mut args)?;
+                    let mut chars = prefix.chars();
+                    config.header_prefix = match (chars.next(), chars.next()) {
+                        (Some(c), None)
+                            if !c.is_alphanumeric()
+                                && !c.is_whitespace()
+                                && !"()[]\"_-.=;:".contains(c) =>
+                        {
+                            c
+                        }
+                        _ => return Err(format!("Invalid header prefix `{prefix}`")),
+                    };
+                }
+                "--delimiter" => {
+                    let delimiter = option_value(&arg, &mut args)?;
+                    let mut chars = delimiter.chars();
+                    config.delimiter = match (delimiter.as_str(), chars.next(), chars.next()) {
+                        ("tab" | "\\t", _, _) => '\t',
+                        (_, Some(c), None) if c.is_ascii() && !matches!(c, '"' | '\n' | '\r') => c,
+                        _ => {
+                            return Err(format!(
+                            "Invalid delimiter `{delimiter}`, expected an ASCII character or `tab`"
+                        ))
+                        }
+                    };
+                }
+                "--columns" => {
+                    let columns = option_value(&arg, &mut args)?;
+                    config.columns = Some(
+                        columns
+                            .split(',')
+                            .map(|letters| {
+                                let letters = letters.trim();
+                                if letters.is_empty()
+                                    || !letters.chars().all(|c| c.is_ascii_uppercase())
+                                {
+                                    return Err(format!(
+                                        "Invalid `--columns` column `{letters}`, expected column letters (e.g. `C,A`)"
+                                    ));
+                                }
+                                utils::col_number_from_alpha(letters).ok_or_else(|| {
+                                    format!("Invalid `--columns` column `{letters}`, it's too large")
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                "--only-rows" => {
+                    config.only_rows = Some(parse_row_ranges(&option_value(&arg, &mut args)?)?)
+                }
+                "--sample" => {
+                    let sample = option_value(&arg, &mut args)?;
+                    config.sample = Some(sample.parse().map_err(|_| {
+                        format!(
+                            "Invalid `--sample` value `{sample}`, expected a non-negative integer"
+                        )
+                    })?);
+                }
+                "--other-rows" => {
+                    config.other_rows = match option_value(&arg, &mut args)?.as_str() {
+                        "drop" => OtherRows::Drop,
+                        "copy" => OtherRows::Copy,
+                        value => {
+                            return Err(format!(
+                                "Invalid `--other-rows` value `{value}`, expected `drop` or `copy`"
+                            ))
+                        }
+                    };
+                }
+                "--rounding-mode" => {
+                    let mode = option_value(&arg, &mut args)?;
+                    config.rounding_mode = config::parse_rounding_mode(&mode).ok_or_else(|| {
+                        format!("Invalid `--rounding-mode` value `{mode}`, expected `half-up`, `half-even`, `floor`, `ceil` or `toward-zero`")
+                    })?;
+                }
+                "--format-profile" => {
+                    config.format_profile = match option_value(&arg, &mut args)?.as_str() {
+                        "default" => FormatProfile::Default,
+                        "excel" => FormatProfile::Excel,
+                        value => {
+                            return Err(format!(
+                                "Invalid `--format-profile` value `{value}`, expected `default` or `excel`"
+                            ))
+                        }
+                    };
+                }
+                "--null-value" => config.null_value = option_value(&arg, &mut args)?,
+                "--output-bom" => config.output_bom = true,
+                "--keep-formulas" => {
+                    let path = PathBuf::from(option_value(&arg, &mut args)?);
+                    utils::check_csv_file_path(&path, false)?;
+                    config.keep_formulas = Some(path)
+                }
+                "--trace-file" => {
+                    config.trace_file = Some(PathBuf::from(option_value(&arg, &mut args)?))
+                }
+                "--cache-file" => {
+                    config.cache_file = Some(PathBuf::from(option_value(&arg, &mut args)?))
+                }
+                "--trailing-newline" => {
+                    config.trailing_newline = match option_value(&arg, &mut args)?.as_str() {
+                        "yes" => true,
+                        "no" => false,
+                        value => {
+                            return Err(format!(
+                            "Invalid `--trailing-newline` value `{value}`, expected `yes` or `no`"
+                        ))
+                        }
+                    };
+                }
+                "--columns-from-row" => {
+                    let row = option_value(&arg, &mut args)?;
+                    config.header_row = row
+                        .parse()
+                        .ok()
+                        .filter(|row| *row != 0)
+                        .ok_or_else(|| format!("Invalid `--columns-from-row` value `{row}`"))?;
+                }
+                "--drop-preamble" => config.drop_preamble = true,
+                "--strict-header" => config.strict_header = true,
+                // The progress bar is only drawn on a terminal
+                "--progress" => config.progress = std::io::stderr().is_terminal(),
+                "--input-format" => {
+                    cli.input_format = match option_value(&arg, &mut args)?.as_str() {
+                        "csv" => InputFormat::Csv,
+                        "json" => InputFormat::Json,
+                        value => {
+                            return Err(format!(
+                                "Invalid `--input-format` value `{value}`, expected `csv` or `json`"
+                            ))
+                        }
+                    };
+                }
+                "--data" => data = Some(option_value(&arg, &mut args)?.replace("\\n", "\n")),
+                "--input-glob" => cli.input_glob = true,
+                "--checksum" => cli.checksum = true,
+                "--explain-errors" => cli.explain_errors = true,
+                "--report-unused-columns" => cli.report_unused_columns = true,
+                "--assert" => cli
+                    .assertions
+                    .push(Assertion::parse(&option_value(&arg, &mut args)?)?),
+                "--detect-types" => detect_types = true,
+                "--dump-deps-dot" => dump_deps_dot = true,
+                "--list-builtins" => list_builtins = true,
+                "--self-test" => self_test = true,
+                option if option.starts_with("--") => {
+                    return Err(format!("Unknown option `{option}`"));
+                }
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        cli.config = config;
+        if self_test {
+            return Ok(Self {
+                action: Action::SelfTest,
+                ..cli
+            });
+        }
+        if list_builtins {
+            return Ok(Self {
+                action: Action::ListBuiltins,
+                ..cli
+            });
+        }
+        if detect_types {
+            if paths.len() != 1 {
+                return Err(format!(
+                    "Expected 1 path with `--detect-types`, found {}",
+                    paths.len()
+                ));
+            }
+            return Ok(Self {
+                action: Action::DetectTypes {
+                    input: paths.pop().expect("there is 1 path"),
+                },
+                ..cli
+            });
+        }
+        if dump_deps_dot {
+            if paths.len() != 1 {
+                return Err(format!(
+                    "Expected 1 path with `--dump-deps-dot`, found {}",
+                    paths.len()
+                ));
+            }
+            return Ok(Self {
+                action: Action::DumpDepsDot {
+                    input: paths.pop().expect("there is 1 path"),
+                },
+                ..cli
+            });
+        }
+        if let Some(data) = data {
+            if paths.len() > 1 {
+                return Err(format!(
+                    "Expected at most 1 path with `--data`, found {}",
+                    paths.len()
+                ));
+            }
+            let output = paths.pop();
+            if cli.checksum && output.is_none() {
+                return Err("The `--checksum` option requires an output file".to_owned());
+            }
+            return Ok(Self {
+                action: Action::RunData { data, output },
+                ..cli
+            });
+        }
+        if paths.len() != 2 {
+            return Err(format!("Expected 2 paths, found {}", paths.len()));
+        }
+        let output = paths.pop().expect("there is 2 paths");
+        let input = paths.pop().expect("there is 2 paths");
+        Ok(Self {
+            action: Action::Run { input, output },
+            ..cli
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the given command line arguments.
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_the_columns() {
+        let cli = parse(&["in.csv", "out.csv", "--columns", "C,A,AA"])
+            .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(cli.config.columns, Some(vec![2, 0, 26]));
+    }
+
+    #[test]
+    fn rejects_overflowing_columns() {
+        let column = "A".repeat(22);
+        let err = parse(&["in.csv", "out.csv", "--columns", &column])
+            .err()
+            .unwrap();
+        assert!(err.contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn accepts_the_progress_flag_off_a_terminal() {
+        let cli = parse(&["in.csv", "out.csv", "--progress"]).unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(cli.config.progress, std::io::stderr().is_terminal());
+    }
+
+    #[test]
+    fn parses_the_assertions() {
+        let assertion = Assertion::parse("C3==4==2").unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            (
+                assertion.cell,
+                assertion.col,
+                assertion.row,
+                assertion.expected
+            ),
+            ("C3".to_owned(), 2, 3, "4==2".to_owned())
+        );
+        for assertion in ["C3=42", "3C==42", "==42"] {
+            assert!(Assertion::parse(assertion)
+                .unwrap_err()
+                .contains("expected `CELL==value`"));
+        }
+    }
+
+    #[test]
+    fn parses_the_row_ranges() {
+        assert_eq!(parse_row_ranges("2-5, 8").unwrap(), [2..=5, 8..=8]);
+        for ranges in ["", "0-2", "5-2", "2-", "a", "2-5,,8", "1-2-3"] {
+            assert!(
+                parse_row_ranges(ranges)
+                    .unwrap_err()
+                    .starts_with("Invalid `--only-rows` range"),
+                "{ranges}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_the_delimiter() {
+        for (delimiter, expected) in [("tab", '\t'), ("\\t", '\t'), (";", ';'), ("|", '|')] {
+            let cli = parse(&["in.csv", "out.csv", "--delimiter", delimiter])
+                .unwrap_or_else(|err| panic!("{err}"));
+            assert_eq!(cli.config.delimiter, expected);
+        }
+        for delimiter in ["", "ab", "\"", "é"] {
+            assert!(parse(&["in.csv", "out.csv", "--delimiter", delimiter])
+                .expect_err("an invalid delimiter")
+                .starts_with("Invalid delimiter"));
+        }
+    }
+}
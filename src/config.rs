@@ -0,0 +1,226 @@
+//! The configuration of the minicel-rs engine.
+
+use std::ops::RangeInclusive;
+
+use bigdecimal::{BigDecimal, RoundingMode};
+
+use crate::encoding::{Encoding, UnmappableChars};
+
+/// The engine configuration.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// The encoding of the output file.
+    pub output_encoding: Encoding,
+    /// What to do with the characters that are not representable in the output encoding.
+    pub unmappable_chars: UnmappableChars,
+    /// The newline style to normalize the newlines inside the output fields to, `None` leaves them unchanged.
+    pub newline_style: Option<NewlineStyle>,
+    /// Write only the formula cells and their results, as `cell,result` records.
+    pub only_formulas: bool,
+    /// The seed of the random number generator, `None` seeds it from the current time.
+    pub seed: Option<u64>,
+    /// The maximum length of an input line in bytes.
+    pub max_line_length: usize,
+    /// Flush the output every `flush_interval` rows, `0` flushes only at the end.
+    pub flush_interval: usize,
+    /// Drop the columns with a duplicate header name or contents, keeping the first one.
+    pub dedupe_columns: bool,
+    /// Read the referenced cells that are exactly `true` or `false` as booleans instead of strings.
+    pub boolean_cells: bool,
+    /// Accept the numeric strings (e.g. `"42"`) as numbers in the numeric builtin functions.
+    pub coerce_text_numbers: bool,
+    /// Read the referenced cells that use `,` as their decimal separator (e.g. `1,5`) as numbers.
+    pub decimal_comma: bool,
+    /// Skip the cells that fail to evaluate in the aggregate builtin functions instead of failing.
+    pub skip_errors: bool,
+    /// Write the records that fail to evaluate unchanged instead of stopping the engine.
+    pub keep_going_rows: bool,
+    /// Write the output to stdout too.
+    pub tee: bool,
+    /// The argument separator accepted in the formulas, in addition to `;`.
+    pub arg_separator: char,
+    /// The columns to write and their order (Starting from 0), applied after the evaluation, `None` writes all the columns.
+    pub columns: Option<Vec<usize>>,
+    /// The value written in place of the empty output cells.
+    pub null_value: String,
+    /// Write a UTF-8 byte order mark at the start of the output, only valid with the UTF-8 output encoding.
+    pub output_bom: bool,
+    /// Reject the header with empty or duplicate names.
+    pub strict_header: bool,
+    /// The maximum number of the arguments of a function call, or the elements of an array.
+    pub max_arguments: usize,
+    /// Draw a progress bar of the processed rows on stderr.
+    pub progress: bool,
+    /// Keep the formulas that call an unknown function unchanged instead of failing.
+    pub allow_unknown_functions: bool,
+    /// The limit of the decimal exponent of the numbers written in the plain notation, the numbers
+    /// beyond it are written in the scientific notation.
+    pub plain_number_limit: usize,
+    /// The row of the header (Starting from 1), the rows above it are the preamble.
+    pub header_row: usize,
+    /// Drop the preamble rows instead of writing them unchanged.
+    pub drop_preamble: bool,
+    /// End the output with a newline, the newline of the last record is stripped if not set.
+    pub trailing_newline: bool,
+    /// The file of the formulas evaluation cache, persisted across the runs.
+    pub cache_file: Option<std::path::PathBuf>,
+    /// The data rows to evaluate and write (Starting from 1), `None` selects all the rows.
+    pub only_rows: Option<Vec<RangeInclusive<usize>>>,
+    /// What to do with the data rows that are not selected by `only_rows`.
+    pub other_rows: OtherRows,
+    /// The maximum size of the output in bytes, the engine fails if it's exceeded.
+    pub max_output_size: u64,
+    /// Accept the numbers `0` and `1` and the strings `yes` and `no` as booleans in the builtin functions.
+    pub coerce_bool: bool,
+    /// The rounding mode of the `round` builtin function, if it's not given to it.
+    pub rounding_mode: RoundingMode,
+    /// The formatting profile of the formulas results.
+    pub format_profile: FormatProfile,
+    /// The maximum length in characters of a string produced by a builtin function.
+    pub max_string_length: usize,
+    /// The CSV field delimiter, an ASCII character other than the quote and the newlines.
+    pub delimiter: char,
+    /// Evaluate the circular references iteratively instead of failing.
+    pub allow_self_reference: bool,
+    /// The maximum iterations of the evaluation of a circular reference.
+    pub max_iterations: usize,
+    /// The maximum change between two iterations of a circular reference to stop iterating.
+    pub iteration_tolerance: BigDecimal,
+    /// Escape the output cells that would be read back as formulas, so the output has only values.
+    pub strip_formulas: bool,
+    /// The file to write the formula cells to, with their original formulas and their results.
+    pub keep_formulas: Option<std::path::PathBuf>,
+    /// The fraction digits of the `pi` and `e` builtin functions, at most [`MAX_CONSTANTS_PRECISION`].
+    pub constants_precision: usize,
+    /// Reject the nondeterministic builtin functions unless there is a `seed`, so the output is the same across the runs.
+    pub deterministic: bool,
+    /// The number of the data rows to evaluate and write, picked randomly from the selected rows by the
+    /// seeded random number generator, `None` writes all the selected rows.
+    pub sample: Option<usize>,
+    /// The prefix of the header-name references in the formulas, e.g. `$age` is the cell of the column
+    /// headed `age` in the row of the formula.
+    pub header_prefix: char,
+    /// The file to write the evaluation of each formula cell to, as JSON lines.
+    pub trace_file: Option<std::path::PathBuf>,
+}
+
+impl EngineConfig {
+    /// Returns the options that change the formulas results, the cached results of other options are stale.
+    pub fn results_fingerprint(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                (
+                    self.boolean_cells,
+                    self.coerce_text_numbers,
+                    self.decimal_comma,
+                    self.coerce_bool,
+                    self.skip_errors,
+                ),
+                self.rounding_mode,
+                self.plain_number_limit,
+                self.max_string_length,
+                self.constants_precision,
+                self.arg_separator,
+                self.header_prefix,
+                self.allow_self_reference,
+                self.max_iterations,
+                &self.iteration_tolerance,
+            )
+        )
+    }
+}
+
+/// The maximum fraction digits of the `pi` and `e` builtin functions.
+pub const MAX_CONSTANTS_PRECISION: usize = 1000;
+
+/// The formatting profiles of the formulas results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatProfile {
+    /// The results as they are evaluated.
+    Default,
+    /// The results as Excel displays and re-imports them, see [`crate::utils::excel_cell`].
+    Excel,
+}
+
+/// What to do with the data rows that are not selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtherRows {
+    /// Drop them from the output.
+    Drop,
+    /// Write them unchanged, without evaluating them.
+    Copy,
+}
+
+/// Parses the given rounding mode, `half-up`, `half-even`, `floor`, `ceil` or `toward-zero`.
+pub fn parse_rounding_mode(mode: &str) -> Option<RoundingMode> {
+    match mode {
+        "half-up" => Some(RoundingMode::HalfUp),
+        "half-even" => Some(RoundingMode::HalfEven),
+        "floor" => Some(RoundingMode::Floor),
+        "ceil" => Some(RoundingMode::Ceiling),
+        "toward-zero" => Some(RoundingMode::Down),
+        _ => None,
+    }
+}
+
+/// The newline styles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewlineStyle {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            output_encoding: Encoding::Utf8,
+            unmappable_chars: UnmappableChars::Error,
+            newline_style: None,
+            only_formulas: false,
+            seed: None,
+            max_line_length: 1024 * 1024,
+            flush_interval: 100,
+            dedupe_columns: false,
+            boolean_cells: true,
+            coerce_text_numbers: false,
+            decimal_comma: false,
+            skip_errors: false,
+            keep_going_rows: false,
+            tee: false,
+            arg_separator: ';',
+            columns: None,
+            null_value: String::new(),
+            output_bom: false,
+            strict_header: false,
+            max_arguments: crate::parser::DEFAULT_MAX_ARGUMENTS,
+            progress: false,
+            allow_unknown_functions: false,
+            plain_number_limit: crate::utils::DEFAULT_PLAIN_NUMBER_LIMIT,
+            header_row: 1,
+            drop_preamble: false,
+            trailing_newline: true,
+            cache_file: None,
+            only_rows: None,
+            other_rows: OtherRows::Drop,
+            max_output_size: 4 * 1024 * 1024 * 1024,
+            coerce_bool: false,
+            rounding_mode: RoundingMode::HalfUp,
+            format_profile: FormatProfile::Default,
+            max_string_length: 1024 * 1024,
+            delimiter: ',',
+            allow_self_reference: false,
+            max_iterations: 100,
+            iteration_tolerance: BigDecimal::new(1.into(), 3),
+            strip_formulas: false,
+            keep_formulas: None,
+            constants_precision: 100,
+            deterministic: false,
+            sample: None,
+            header_prefix: '$',
+            trace_file: None,
+        }
+    }
+}
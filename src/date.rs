@@ -0,0 +1,153 @@
+//! The ISO 8601 calendar dates used by the date builtin functions.
+
+/// A calendar date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Returns whether the given year is a leap year.
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Returns the number of days in the given month.
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Date {
+    /// Parses an ISO 8601 date, `YYYY-MM-DD`. The date can be followed by a time after a `T` or a space,
+    /// the time is ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let date = text.split_once(['T', ' ']).map_or(text, |(date, _)| date);
+        let invalid = || format!("Invalid date `{text}`, expected `YYYY-MM-DD`");
+
+        let mut parts = date.split('-');
+        let (Some(year), Some(month), Some(day), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(invalid());
+        }
+        let number = |part: &str| {
+            part.chars()
+                .all(|c| c.is_ascii_digit())
+                .then(|| part.parse::<u32>().ok())
+                .flatten()
+                .ok_or_else(invalid)
+        };
+        let (year, month, day) = (number(year)? as i32, number(month)?, number(day)?);
+
+        if !(1..=12).contains(&month) {
+            return Err(format!(
+                "Invalid date `{text}`, the month {month} is out of range"
+            ));
+        }
+        if !(1..=days_in_month(year, month)).contains(&day) {
+            return Err(format!(
+                "Invalid date `{text}`, the day {day} is out of range"
+            ));
+        }
+        Ok(Self { year, month, day })
+    }
+
+    /// Returns the ISO weekday of the date, 1 is Monday and 7 is Sunday.
+    pub fn weekday(&self) -> u32 {
+        // Days since 1970-01-01, a Thursday. (Howard Hinnant's `days_from_civil`)
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        } as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+        ((days + 3).rem_euclid(7) + 1) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_leap_days() {
+        assert!(Date::parse("2000-02-29").is_ok());
+        assert!(Date::parse("2024-02-29").is_ok());
+        assert!(Date::parse("1900-02-29").is_err());
+        assert!(Date::parse("2023-02-29").is_err());
+    }
+
+    #[test]
+    fn parses_the_month_boundaries() {
+        for date in [
+            "2023-01-31",
+            "2023-02-28",
+            "2023-04-30",
+            "2023-12-31",
+            "2023-01-01",
+        ] {
+            assert!(Date::parse(date).is_ok(), "{date}");
+        }
+        for date in [
+            "2023-01-32",
+            "2023-04-31",
+            "2023-01-00",
+            "2023-00-10",
+            "2023-13-01",
+        ] {
+            assert!(Date::parse(date).is_err(), "{date}");
+        }
+    }
+
+    #[test]
+    fn parses_the_date_parts() {
+        assert_eq!(
+            Date::parse(" 2024-03-05T10:30:00 "),
+            Ok(Date {
+                year: 2024,
+                month: 3,
+                day: 5
+            })
+        );
+        for date in [
+            "2024-3-05",
+            "24-03-05",
+            "2024/03/05",
+            "2024-03-+5",
+            "2024-03-05-01",
+        ] {
+            assert!(Date::parse(date).is_err(), "{date}");
+        }
+    }
+
+    #[test]
+    fn computes_the_weekdays() {
+        for (date, weekday) in [
+            ("1900-01-01", 1),
+            ("1970-01-01", 4),
+            ("2000-01-01", 6),
+            ("2000-02-29", 2),
+            ("2000-03-01", 3),
+            ("2023-12-31", 7),
+            ("2024-01-01", 1),
+        ] {
+            assert_eq!(Date::parse(date).unwrap().weekday(), weekday, "{date}");
+        }
+    }
+}
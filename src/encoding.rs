@@ -0,0 +1,155 @@
+//! The output encodings of the minicel-rs, the engine works with UTF-8 and re-encodes the output while writing it.
+
+use std::io::{self, Write};
+
+/// The supported output encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8, the engine output is written as is.
+    Utf8,
+    /// ISO-8859-1 (Latin-1), each character is written as a single byte.
+    Latin1,
+}
+
+/// What to do with the characters that are not representable in the output encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmappableChars {
+    /// Return an error.
+    Error,
+    /// Replace the character with `?`.
+    Replace,
+}
+
+impl Encoding {
+    /// Returns the encoding from its label. e.g. `utf-8`, `latin1`, `iso-8859-1`
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Self::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the encoding.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Utf8 => "UTF-8",
+            Self::Latin1 => "ISO-8859-1",
+        }
+    }
+
+    /// Encodes the given UTF-8 text.
+    pub fn encode(&self, text: &str, unmappable: UnmappableChars) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Utf8 => Ok(text.as_bytes().to_vec()),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| match u8::try_from(u32::from(c)) {
+                    Ok(byte) => Ok(byte),
+                    Err(_) if unmappable == UnmappableChars::Replace => Ok(b'?'),
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("The character `{c}` cannot be encoded in {}", self.name()),
+                    )),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A writer that re-encodes the UTF-8 bytes written to it before passing them to the inner writer.
+#[derive(Debug)]
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    encoding: Encoding,
+    unmappable: UnmappableChars,
+    /// The bytes of an incomplete character, waiting for the rest of its bytes.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    /// Creates a new encoding writer.
+    pub fn new(inner: W, encoding: Encoding, unmappable: UnmappableChars) -> Self {
+        Self {
+            inner,
+            encoding,
+            unmappable,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Utf8 {
+            return self.inner.write(buf);
+        }
+
+        self.pending.extend_from_slice(buf);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid_up_to]).expect("is valid UTF-8");
+        let encoded = self.encoding.encode(text, self.unmappable)?;
+        self.inner.write_all(&encoded)?;
+        self.pending.drain(..valid_up_to);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_accented_characters_in_latin1() {
+        assert_eq!(
+            Encoding::Latin1
+                .encode("café, Zürich", UnmappableChars::Error)
+                .unwrap(),
+            b"caf\xe9, Z\xfcrich"
+        );
+        assert_eq!(
+            Encoding::Utf8.encode("é", UnmappableChars::Error).unwrap(),
+            "é".as_bytes()
+        );
+    }
+
+    #[test]
+    fn handles_the_unmappable_characters() {
+        assert_eq!(
+            Encoding::Latin1
+                .encode("1€", UnmappableChars::Replace)
+                .unwrap(),
+            b"1?"
+        );
+        assert!(Encoding::Latin1
+            .encode("1€", UnmappableChars::Error)
+            .unwrap_err()
+            .to_string()
+            .contains("`€` cannot be encoded in ISO-8859-1"));
+    }
+
+    #[test]
+    fn encodes_the_characters_split_between_the_writes() {
+        let mut writer = EncodingWriter::new(Vec::new(), Encoding::Latin1, UnmappableChars::Error);
+        let bytes = "né".as_bytes();
+        writer.write_all(&bytes[..2]).unwrap();
+        writer.write_all(&bytes[2..]).unwrap();
+        assert_eq!(writer.inner, b"n\xe9");
+    }
+
+    #[test]
+    fn parses_the_labels() {
+        assert_eq!(Encoding::from_label("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::from_label("iso-8859-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_label("latin-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_label("windows-1252"), None);
+    }
+}
@@ -1,142 +1,1096 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    ast::{self, Expression},
+    ast::{self, Expression, Span},
     builtins,
+    cache::EvaluationCache,
+    config::{EngineConfig, FormatProfile, OtherRows},
+    date::Date,
+    encoding::{Encoding, EncodingWriter},
     errors::{Error as MinicelError, ErrorKind as MinicelErrorKind, Result as MinicelResult},
-    parser, tokenizer, utils,
+    json::JsonValue,
+    parser,
+    rng::Rng,
+    tokenizer, utils,
+    value::{ErrorCode, Value},
 };
 
+/// A cell of the sheet. (column, row)
+pub type Cell = (usize, u64);
+
+/// Returns the column number (Starting from 0) of the given column letters, an error if it overflows.
+fn column_number(col: &str, line_number: usize) -> MinicelResult<usize> {
+    utils::col_number_from_alpha(col).ok_or_else(|| {
+        MinicelError::new(
+            MinicelErrorKind::Engine,
+            format!("Invalid column `{col}`, it's too large"),
+            line_number,
+        )
+    })
+}
+
+/// Returns the error of writing the output of the given line. The encoding errors and the maximum output
+/// size error are reported as is.
+fn output_error(err: io::Error, line_number: usize) -> MinicelError {
+    let message = match err.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::Other => err.to_string(),
+        _ => format!("Write CSV file error `{}`", err),
+    };
+    MinicelError::new(MinicelErrorKind::Engine, message, line_number)
+}
+
+/// Serializes the given record and writes it to the output right away, so the output errors are reported
+/// at the line of the record instead of when the CSV writer flushes its buffer.
+fn write_output_record<I, T>(
+    writer: &mut csv::Writer<utils::SharedBuffer>,
+    output: &mut impl Write,
+    record: I,
+    line_number: usize,
+) -> MinicelResult<()>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    writer
+        .write_record(record)
+        .and_then(|()| Ok(writer.flush()?))
+        .map_err(|err| {
+            MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!("Write CSV record error `{}`", err),
+                line_number,
+            )
+        })?;
+    writer
+        .get_ref()
+        .write_to(output)
+        .map_err(|err| output_error(err, line_number))
+}
+
+/// Replaces the fields of the given expressions with their given values, parsed like the evaluated
+/// fields. The ranges are expanded to their fields first.
+fn substitute_fields(
+    expressions: Vec<Expression>,
+    values: &HashMap<(String, u64), String>,
+    boolean_cells: bool,
+    decimal_comma: bool,
+) -> Vec<Expression> {
+    expressions
+        .into_iter()
+        .flat_map(Expression::expand_range)
+        .map(|expression| match expression {
+            Expression::Field { col, row, value } => match values.get(&(col.clone(), row)) {
+                Some(value) => {
+                    utils::parse_string_to_expression(value.clone(), boolean_cells, decimal_comma)
+                }
+                None => Expression::Field { col, row, value },
+            },
+            Expression::FunctionCall(mut function_call) => {
+                substitute_arguments(&mut function_call, values, boolean_cells, decimal_comma);
+                Expression::FunctionCall(function_call)
+            }
+            Expression::Array(array) => Expression::Array(substitute_fields(
+                array,
+                values,
+                boolean_cells,
+                decimal_comma,
+            )),
+            expression => expression,
+        })
+        .collect()
+}
+
+/// Replaces the fields of the arguments of the given function call like [`substitute_fields`], the
+/// fields of an expanded range keep the range span.
+fn substitute_arguments(
+    function_call: &mut ast::FunctionCallExpression,
+    values: &HashMap<(String, u64), String>,
+    boolean_cells: bool,
+    decimal_comma: bool,
+) {
+    let arguments = std::mem::take(&mut function_call.arguments);
+    function_call.argument_spans = expanded_spans(
+        &arguments,
+        std::mem::take(&mut function_call.argument_spans),
+    );
+    function_call.arguments = substitute_fields(arguments, values, boolean_cells, decimal_comma);
+}
+
+/// Returns the spans of the given arguments after expanding their ranges, the fields of a range
+/// have the range span.
+fn expanded_spans(arguments: &[Expression], spans: Vec<Span>) -> Vec<Span> {
+    arguments
+        .iter()
+        .zip(spans)
+        .flat_map(|(argument, span)| {
+            let fields = match argument {
+                Expression::Range { .. } => argument.range_fields().len(),
+                _ => 1,
+            };
+            std::iter::repeat(span).take(fields)
+        })
+        .collect()
+}
+
+/// The UTF-8 byte order mark.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
 /// The minicel-rs engine.
 #[derive(Debug)]
 pub struct Engine<'a> {
     /// The file
     pub file: std::path::PathBuf,
-    /// Updated fields to be written back to the CSV file.
-    /// (record, value)
-    pub updated_records: Vec<(u64, Vec<String>)>,
-    /// The csv lines
-    // FIXME: This is bad, but this is not a product use project, so yeah
-    pub lines: Vec<&'a str>,
+    /// Updated fields to be written back to the CSV file, the updated records by row.
+    pub updated_records: HashMap<u64, Vec<String>>,
+    /// The csv records, the header is the first one
+    pub records: Vec<Vec<String>>,
+    /// The line number of the start of each record in the file
+    record_lines: Vec<usize>,
+    /// The lines above the header, written unchanged unless `drop_preamble` is set
+    pub preamble: Vec<&'a str>,
     /// The count of csv rows 1-based
     rows: usize,
+    /// The engine configuration
+    config: EngineConfig,
+    /// The random number generator used by the builtin functions
+    rng: Rng,
+    /// The formulas evaluation cache, if there is a cache file
+    cache: Option<EvaluationCache>,
+    /// The record that is being evaluated, with its evaluated fields so far.
+    /// (record, fields)
+    current_record: Option<(u64, Vec<String>)>,
+    /// The cells that are being evaluated, to detect the circular references.
+    /// (column, row)
+    evaluating: Vec<(usize, u64)>,
+    /// The values of the previous iteration of the cells that reference themselves, with `allow_self_reference`.
+    iterated_cells: HashMap<(usize, u64), String>,
+    /// The errors of the cells that failed and are written as their error code, with the `excel`
+    /// format profile. A reference to them fails with their error.
+    failed_cells: HashMap<(usize, u64), MinicelError>,
+    /// The trace file of the formula cells evaluation, while the engine is running with a `trace_file`.
+    trace: Option<BufWriter<File>>,
+}
+
+/// The inferred type of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Number,
+    String,
+    Boolean,
+    /// An ISO 8601 date string. e.g. `2024-03-15`
+    Date,
+    /// The column has values of different types
+    Mixed,
+    /// The column has no values
+    Empty,
+}
+
+impl ColumnType {
+    /// Returns the type of the given evaluated value.
+    fn of(value: &str, boolean_cells: bool, decimal_comma: bool) -> Self {
+        match utils::parse_string_to_expression(value.to_owned(), boolean_cells, decimal_comma) {
+            Expression::Number(_) => Self::Number,
+            Expression::Boolean(_) => Self::Boolean,
+            _ if Date::parse(value).is_ok() => Self::Date,
+            _ => Self::String,
+        }
+    }
+
+    /// Returns the name of the type.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Boolean => "boolean",
+            Self::Date => "date",
+            Self::Mixed => "mixed",
+            Self::Empty => "empty",
+        }
+    }
+}
+
+/// Returns the error message of a call of an unknown function, with the closest builtin function name.
+fn unknown_function_message(name: &str) -> String {
+    match builtins::closest_builtin(name) {
+        Some(builtin) => format!("Unknown function {name}, did you mean {builtin}?"),
+        None => format!("Unknown function {name}"),
+    }
+}
+
+/// Returns the error of a reference to the header row.
+fn header_reference_error(line_number: usize) -> MinicelError {
+    MinicelError::new(
+        MinicelErrorKind::Engine,
+        "Invalid row number 0, the header can't be referenced".to_owned(),
+        line_number,
+    )
+    .with_code(ErrorCode::InvalidReference)
+}
+
+/// Checks that the header names are unique and non-empty.
+fn check_header(header: &[String], line_number: usize) -> MinicelResult<()> {
+    let names = header.iter().map(|name| name.trim()).collect::<Vec<_>>();
+    let mut problems = Vec::new();
+
+    let empty_columns = names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| name.is_empty())
+        .map(|(col, _)| utils::col_alpha_from_number(col))
+        .collect::<Vec<_>>();
+    if !empty_columns.is_empty() {
+        problems.push(format!("empty names in {}", empty_columns.join(", ")));
+    }
+    for (col, name) in names.iter().enumerate() {
+        if name.is_empty() || names[..col].contains(name) {
+            continue;
+        }
+        let columns = names
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| other == &name)
+            .map(|(col, _)| utils::col_alpha_from_number(col))
+            .collect::<Vec<_>>();
+        if columns.len() > 1 {
+            problems.push(format!("`{name}` is duplicated in {}", columns.join(", ")));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(MinicelError::new(
+            MinicelErrorKind::Engine,
+            format!("CSV error: Invalid header, {}", problems.join("; ")),
+            line_number,
+        ))
+    }
+}
+
+/// Returns whether the function call of the evaluated argument skips the error cells, the argument
+/// of an `iferror` value doesn't, the `iferror` catches its error.
+fn skips_errors(tasks: &[EvaluationTask]) -> bool {
+    tasks.iter().rev().find_map(|task| match task {
+        EvaluationTask::Call { name, .. } => {
+            Some(builtins::ERROR_SKIPPING_BUILTINS.contains(&name.as_str()))
+        }
+        EvaluationTask::Catch { .. } => Some(false),
+        _ => None,
+    }) == Some(true)
+}
+
+/// A task of the function call evaluation work stack.
+#[derive(Debug)]
+enum EvaluationTask {
+    /// Evaluate the expression and push its value. (expression, line number)
+    Evaluate(Expression, usize),
+    /// Call the builtin function with the last `arguments_count` values.
+    Call {
+        name: String,
+        arguments_count: usize,
+        line_number: usize,
+        /// The spans of the arguments
+        argument_spans: Vec<Span>,
+    },
+    /// Collect the last `elements_count` values into an array.
+    Array(usize),
+    /// The `iferror` value is evaluated above this task, on error the values are truncated
+    /// to `values_count` and the fallback is evaluated instead.
+    Catch {
+        fallback: Expression,
+        values_count: usize,
+        line_number: usize,
+    },
+}
+
+/// The iterator of the evaluated records of the engine, see [`Engine::evaluated_rows`].
+#[derive(Debug)]
+pub struct EvaluatedRows<'e, 'a> {
+    engine: &'e mut Engine<'a>,
+    /// The next row to evaluate
+    row: usize,
+}
+
+impl Iterator for EvaluatedRows<'_, '_> {
+    type Item = MinicelResult<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(record) = self.engine.records.get(self.row).cloned() {
+            let row = self.row;
+            self.row += 1;
+            if !record.is_empty() {
+                return Some(
+                    self.engine
+                        .evaluate_record(row, &record, &[])
+                        .map(|(record, _)| record),
+                );
+            }
+        }
+        None
+    }
 }
 
 impl<'a> Engine<'a> {
     /// Creates a new engine from the given CSV file.
-    pub fn new(csv_path: PathBuf, csv_str: &'a str) -> MinicelResult<Self> {
+    pub fn new(csv_path: PathBuf, csv_str: &'a str, config: EngineConfig) -> MinicelResult<Self> {
+        if !config.delimiter.is_ascii() || matches!(config.delimiter, '"' | '\n' | '\r') {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "CSV error: Invalid delimiter `{}`, expected an ASCII character other than the quote and the newlines",
+                    config.delimiter.escape_default()
+                ),
+                0,
+            ));
+        }
+        if config.header_prefix == config.arg_separator {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "The header prefix `{}` is the argument separator of the formulas, set another one with `--header-prefix`",
+                    config.header_prefix
+                ),
+                0,
+            ));
+        }
+        if config.delimiter == config.arg_separator {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "CSV error: The delimiter `{}` is the argument separator of the formulas, set another one with `--arg-separator`",
+                    config.delimiter.escape_default()
+                ),
+                0,
+            ));
+        }
+        if config.decimal_comma && config.delimiter == ',' {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                "CSV error: The decimal comma can't be used with the `,` delimiter, set another one with `--delimiter`"
+                    .to_owned(),
+                0,
+            ));
+        }
         let lines = csv_str.lines();
+        if let Some((line_idx, line)) = lines
+            .clone()
+            .enumerate()
+            .find(|(_, line)| line.len() > config.max_line_length)
+        {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "CSV error: The line is {} bytes long, the maximum line length is {} bytes",
+                    line.len(),
+                    config.max_line_length
+                ),
+                line_idx + 1,
+            ));
+        }
+
+        let mut lines = csv_str.lines().collect::<Vec<_>>();
+        let empty_input_error = || {
+            MinicelError::new(
+                MinicelErrorKind::Engine,
+                "CSV error: The input is empty, expected at least the header".to_owned(),
+                0,
+            )
+        };
+        if lines.is_empty() {
+            return Err(empty_input_error());
+        }
+        let preamble_len = config.header_row - 1;
+        if preamble_len >= lines.len() {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "CSV error: The header row {} is beyond the {} lines of the file",
+                    config.header_row,
+                    lines.len()
+                ),
+                0,
+            ));
+        }
+        lines.truncate(preamble_len);
+        let sheet_start = csv_str
+            .split_inclusive('\n')
+            .take(preamble_len)
+            .map(str::len)
+            .sum::<usize>();
+
+        // The records are parsed as RFC 4180, the quoted fields can contain the delimiter,
+        // the escaped quotes and the newlines.
+        let sheet = &csv_str.as_bytes()[sheet_start..];
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(config.delimiter as u8)
+            .from_reader(sheet);
+        let mut records = Vec::new();
+        let mut record_lines = Vec::new();
+        // The line number and the byte offset of the start of the last record
+        let (mut line_number, mut offset) = (preamble_len + 1, 0);
+        for record in reader.records() {
+            let record = record.map_err(|err| {
+                let line_number = err
+                    .position()
+                    .map_or(0, |position| preamble_len + position.line() as usize);
+                MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("CSV error: {err}"),
+                    line_number,
+                )
+            })?;
+            // The position of a record after empty lines is the start of the empty lines
+            let start = record
+                .position()
+                .map_or(offset, |position| position.byte() as usize);
+            let start = start
+                + sheet[start..]
+                    .iter()
+                    .take_while(|byte| matches!(byte, b'\n' | b'\r'))
+                    .count();
+            line_number += sheet[offset..start]
+                .iter()
+                .filter(|byte| **byte == b'\n')
+                .count();
+            offset = start;
+            record_lines.push(line_number);
+            records.push(record.iter().map(str::to_owned).collect::<Vec<_>>());
+        }
+        if records.is_empty() {
+            return Err(empty_input_error());
+        }
+
+        if config.strict_header {
+            check_header(&records[0], record_lines[0])?;
+        }
+
         Ok(Self {
-            updated_records: Vec::new(),
+            updated_records: HashMap::new(),
             file: csv_path.to_path_buf(),
             // Minus the csv header
-            rows: lines.clone().count() - 1,
-            lines: csv_str.lines().collect(),
+            rows: records.len() - 1,
+            records,
+            record_lines,
+            preamble: lines,
+            rng: config.seed.map(Rng::new).unwrap_or_else(Rng::from_time),
+            cache: config
+                .cache_file
+                .as_deref()
+                .map(EvaluationCache::load)
+                .transpose()
+                .map_err(|err| MinicelError::new(MinicelErrorKind::Engine, err, 0))?,
+            config,
+            current_record: None,
+            evaluating: Vec::new(),
+            iterated_cells: HashMap::new(),
+            failed_cells: HashMap::new(),
+            trace: None,
         })
     }
 
+    /// Returns whether the given row is the header, the header is never evaluated nor referenced.
+    pub fn is_header_row(&self, row: usize) -> bool {
+        row == 0
+    }
+
+    /// Returns whether the given data row is selected by `only_rows`.
+    fn is_only_row(&self, row: usize) -> bool {
+        self.config.only_rows.as_ref().map_or(true, |only_rows| {
+            only_rows.iter().any(|rows| rows.contains(&row))
+        })
+    }
+
+    /// Picks randomly the given number of data rows from the selected rows, sorted.
+    fn sample_rows(&mut self, sample: usize) -> Vec<usize> {
+        let mut rows = (1..self.records.len())
+            .filter(|row| !self.records[*row].is_empty() && self.is_only_row(*row))
+            .collect::<Vec<_>>();
+        self.rng.shuffle(&mut rows);
+        rows.truncate(sample);
+        rows.sort_unstable();
+        rows
+    }
+
+    /// Returns the line number of the given row in the file.
+    fn line_number(&self, row: usize) -> usize {
+        self.record_lines
+            .get(row)
+            .copied()
+            .unwrap_or(self.preamble.len() + row + 1)
+    }
+
     /// Runs the given function call.
-    #[allow(clippy::only_used_in_recursion)]
     pub fn function_call(
         &mut self,
-        mut function_call: ast::FunctionCallExpression,
+        function_call: ast::FunctionCallExpression,
     ) -> MinicelResult<String> {
+        self.evaluate_function_call(function_call)
+            .map(|value| value.to_string())
+    }
+
+    /// Runs the given function call, returns its typed value.
+    ///
+    /// The nested function calls are evaluated with an explicit work stack instead of recursion,
+    /// so the nesting depth is not bounded by the call stack.
+    fn evaluate_function_call(
+        &mut self,
+        function_call: ast::FunctionCallExpression,
+    ) -> MinicelResult<Expression> {
         log::info!("Running function call: {function_call:#?}");
 
-        for arg in function_call.arguments.iter_mut() {
-            if let Expression::FunctionCall(arg_function_call) = arg {
-                log::debug!(
-                    "Found function call in `{}` args: {arg_function_call:?}",
-                    function_call.name
-                );
-                let value = self.function_call(arg_function_call.clone())?;
-                *arg = utils::parse_string_to_expression(value);
+        let mut tasks = vec![EvaluationTask::Evaluate(
+            Expression::FunctionCall(function_call),
+            0,
+        )];
+        let mut values: Vec<Expression> = Vec::new();
+
+        while let Some(task) = tasks.pop() {
+            match self.run_task(task, &mut tasks, &mut values) {
+                Ok(Some(value)) if tasks.is_empty() => return Ok(value),
+                // The text results are read like the cells, e.g. `"3"` is a number
+                Ok(Some(Expression::String(value))) => {
+                    values.push(utils::parse_string_to_expression(
+                        value,
+                        self.config.boolean_cells,
+                        self.config.decimal_comma,
+                    ))
+                }
+                Ok(Some(value)) => values.push(value),
+                Ok(None) => {}
+                Err(error) => {
+                    // Unwind to the nearest `iferror`, if there is one.
+                    loop {
+                        match tasks.pop() {
+                            Some(EvaluationTask::Catch {
+                                fallback,
+                                values_count,
+                                line_number,
+                            }) => {
+                                log::info!(
+                                    "iferror value failed, evaluating the fallback: {error}"
+                                );
+                                values.truncate(values_count);
+                                tasks.push(EvaluationTask::Evaluate(fallback, line_number));
+                                break;
+                            }
+                            Some(_) => continue,
+                            None => return Err(error),
+                        }
+                    }
+                }
             }
         }
 
-        if let Some(builtin) = builtins::call_builtin(&function_call.name, function_call.arguments)
-        {
-            log::info!(
-                "Running {} builtin function successfully",
-                function_call.name
-            );
-            match builtin {
-                Ok(value) => {
-                    log::debug!("Builtin function returned: {value}");
-                    Ok(value)
+        Ok(values
+            .pop()
+            .expect("The evaluated function call leaves its value"))
+    }
+
+    /// Runs the given evaluation task, returns the value of the task if it produced a value of a
+    /// function call or a field.
+    fn run_task(
+        &mut self,
+        task: EvaluationTask,
+        tasks: &mut Vec<EvaluationTask>,
+        values: &mut Vec<Expression>,
+    ) -> MinicelResult<Option<Expression>> {
+        match task {
+            EvaluationTask::Evaluate(Expression::FunctionCall(function_call), _) => {
+                let line_number = function_call.line_number;
+                if function_call.name == "iferror" {
+                    let [value, fallback]: [Expression; 2] =
+                        function_call.arguments.try_into().map_err(|args: Vec<_>| {
+                            MinicelError::new(
+                                MinicelErrorKind::Engine,
+                                format!(
+                                    "Builtin function error: Expected 2 arguments, found {}",
+                                    args.len()
+                                ),
+                                line_number,
+                            )
+                        })?;
+                    tasks.push(EvaluationTask::Catch {
+                        fallback,
+                        values_count: values.len(),
+                        line_number,
+                    });
+                    tasks.push(EvaluationTask::Evaluate(value, line_number));
+                } else {
+                    // The ranges are expanded to their fields
+                    let argument_spans =
+                        expanded_spans(&function_call.arguments, function_call.argument_spans);
+                    let arguments = function_call
+                        .arguments
+                        .into_iter()
+                        .flat_map(Expression::expand_range)
+                        .collect::<Vec<_>>();
+                    tasks.push(EvaluationTask::Call {
+                        name: function_call.name,
+                        arguments_count: arguments.len(),
+                        line_number,
+                        argument_spans,
+                    });
+                    tasks.extend(
+                        arguments
+                            .into_iter()
+                            .rev()
+                            .map(|argument| EvaluationTask::Evaluate(argument, line_number)),
+                    );
                 }
-                Err(error) => {
-                    log::error!("Builtin function error: {error}");
-                    Err(MinicelError::new(
+                Ok(None)
+            }
+            EvaluationTask::Evaluate(Expression::Field { col, row, .. }, line_number) => {
+                log::debug!("Evaluating field argument Col: {col}, Row: {row}");
+                match self.get_field(column_number(&col, line_number)?, row, line_number) {
+                    Err(err) if self.config.skip_errors && skips_errors(tasks) => {
+                        log::info!("The field {col}{row} failed, passing its error code: {err}");
+                        values.push(Expression::Error(err.code));
+                        Ok(None)
+                    }
+                    result => result.map(|value| Some(Expression::String(value))),
+                }
+            }
+            EvaluationTask::Evaluate(Expression::Array(array), line_number) => {
+                let array = array
+                    .into_iter()
+                    .flat_map(Expression::expand_range)
+                    .collect::<Vec<_>>();
+                tasks.push(EvaluationTask::Array(array.len()));
+                tasks.extend(
+                    array
+                        .into_iter()
+                        .rev()
+                        .map(|element| EvaluationTask::Evaluate(element, line_number)),
+                );
+                Ok(None)
+            }
+            EvaluationTask::Evaluate(range @ Expression::Range { .. }, line_number) => {
+                Err(MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("Unexpected range `{range}`, a range is only a function argument or an array element"),
+                    line_number,
+                ))
+            }
+            EvaluationTask::Evaluate(argument, _) => {
+                values.push(argument);
+                Ok(None)
+            }
+            EvaluationTask::Array(elements_count) => {
+                let elements = values.split_off(values.len() - elements_count);
+                values.push(Expression::Array(elements));
+                Ok(None)
+            }
+            EvaluationTask::Catch { .. } => Ok(Some(
+                values.pop().expect("The iferror value is evaluated"),
+            )),
+            EvaluationTask::Call {
+                name,
+                arguments_count,
+                line_number,
+                argument_spans,
+            } => {
+                let arguments = values.split_off(values.len() - arguments_count);
+                let span = builtins::mistyped_arguments(&name, &arguments, &self.config)
+                    .first()
+                    .and_then(|(index, _)| argument_spans.get(*index).cloned());
+                let mut ctx = builtins::Context {
+                    config: &self.config,
+                    rng: &mut self.rng,
+                };
+                match builtins::call_builtin(&name, arguments, &mut ctx) {
+                    Some(Ok(value)) => {
+                        log::info!("Running {name} builtin function successfully");
+                        log::debug!("Builtin function returned: {value}");
+                        Ok(Some(value))
+                    }
+                    Some(Err(error)) => {
+                        // Not an error log, an enclosing `iferror` may catch it
+                        log::debug!("Builtin function error: {error}");
+                        Err(MinicelError::new(
+                            MinicelErrorKind::Engine,
+                            format!("Builtin function error: {}", error.message),
+                            line_number,
+                        )
+                        .with_code(error.code)
+                        .with_span(span))
+                    }
+                    None => Err(MinicelError::new(
                         MinicelErrorKind::Engine,
-                        format!("Builtin function error: {error}"),
-                        function_call.line_number,
-                    ))
+                        unknown_function_message(&name),
+                        line_number,
+                    )
+                    .with_code(ErrorCode::UnknownFunction)),
                 }
             }
-        } else {
-            Err(MinicelError::new(
-                MinicelErrorKind::Engine,
-                format!("Unknown function {}", function_call.name),
-                function_call.line_number,
-            ))
         }
     }
 
-    /// Executes the given field if it is a function call.
-    pub fn execute_field(&mut self, field: String, line_number: usize) -> MinicelResult<String> {
-        log::info!("Executing field \"{field}\" at line {line_number}");
+    /// Parses the given formula field of the given row, starting with `=`.
+    fn parse_formula(&self, field: &str, row: u64, line_number: usize) -> MinicelResult<ast::Ast> {
+        let (tokens, spans) = tokenizer::tokenize_cell(
+            field,
+            line_number,
+            self.config.arg_separator,
+            self.config.header_prefix,
+        )?
+        .unwrap_or_default();
+        log::debug!("Field tokens: {tokens:?}");
+        let mut parser = parser::Parser::new(multipeek::multipeek(tokens.iter()), line_number)
+            .with_spans(&spans)
+            .with_max_arguments(self.config.max_arguments)
+            .with_header(&self.records[0], row)
+            .with_rows(self.rows as u64);
+        log::debug!("Field parser: {parser:#?}");
+        parser.parse()
+    }
 
-        if field.starts_with('=') {
-            log::info!("Field is a function call");
+    /// Checks the formulas of the sheet without evaluating them, returns all the found errors.
+    ///
+    /// The formulas are parsed, the referenced cells must exist, the functions must be builtin
+    /// functions (unless `allow_unknown_functions` is set) and the literal arguments of the
+    /// arithmetic functions must be numbers.
+    pub fn validate(&self) -> Vec<MinicelError> {
+        let mut diagnostics = Vec::new();
+        for (row, record) in self.records.iter().enumerate().skip(1) {
+            let line_number = self.line_number(row);
+            for field in record.iter().map(|field| field.trim()) {
+                if !field.starts_with('=') {
+                    continue;
+                }
+                match self.parse_formula(field, row as u64, line_number) {
+                    Ok(ast) => {
+                        self.check_formula(field, ast.function, line_number, &mut diagnostics)
+                    }
+                    Err(err) => diagnostics.push(err),
+                }
+            }
+        }
+        diagnostics
+    }
 
-            let tokens = tokenizer::tokenize(field.trim_start_matches('=').trim(), line_number)?;
-            log::debug!("Field tokens: {tokens:?}");
-            let mut parser = parser::Parser::new(multipeek::multipeek(tokens.iter()), line_number);
-            log::debug!("Field parser: {parser:#?}");
-            let mut ast = parser.parse()?;
-
-            log::info!("Executing child expressions");
-            for expr in ast.mut_children() {
-                log::debug!("Executing child expression: {expr:#?}");
-
-                if let Expression::FunctionCall(function_call) = expr {
-                    log::debug!("Child expression is a function call");
-                    let value = self.function_call(function_call.clone())?;
-                    *expr = Expression::String(value);
-                } else if let Expression::Array(array) = expr {
-                    log::debug!("Child expression is an array");
-                    log::info!("Executing child expressions in array");
-                    for element in array {
-                        log::debug!("Executing child expression in array: {element:#?}");
-                        if let Expression::FunctionCall(function_call) = element {
-                            log::debug!("Child expression in array is a function call");
-                            let value = self.function_call(function_call.clone())?;
-                            *element = Expression::String(value);
-                        } else if let Expression::Field { col, row, .. } = element {
-                            log::debug!(
-                                "Child expression in array is a field Col: {col}, Row: {row}"
-                            );
-                            let value = self.get_field(
-                                utils::col_number_from_alpha(col),
-                                *row,
-                                line_number,
-                            )?;
-                            *element = utils::parse_string_to_expression(value);
-                        }
+    /// Returns the cells dependency graph, each formula cell with the cells it references, in the sheet
+    /// order. The column of the cells starts from 0 and the row from 1.
+    pub fn dependencies(&self) -> MinicelResult<Vec<(Cell, Vec<Cell>)>> {
+        let mut dependencies = Vec::new();
+        for (row, record) in self.records.iter().enumerate().skip(1) {
+            let line_number = self.line_number(row);
+            for (col, field) in record.iter().map(|field| field.trim()).enumerate() {
+                if !field.starts_with('=') {
+                    continue;
+                }
+                let ast = self.parse_formula(field, row as u64, line_number)?;
+                let mut references = Vec::new();
+                for (ref_col, ref_row) in ast.function.referenced_fields() {
+                    let reference = (column_number(&ref_col, line_number)?, ref_row);
+                    if !references.contains(&reference) {
+                        references.push(reference);
+                    }
+                }
+                dependencies.push(((col, row as u64), references));
+            }
+        }
+        Ok(dependencies)
+    }
+
+    /// Returns the cells dependency graph in the GraphViz DOT format, the nodes are the cells in the A1
+    /// notation and the edges are from the formula cells to the cells they reference.
+    pub fn dependencies_dot(&self) -> MinicelResult<String> {
+        let cell_name =
+            |(col, row): Cell| format!("\"{}{row}\"", utils::col_alpha_from_number(col));
+        let mut dot = String::from("digraph dependencies {\n");
+        for (cell, references) in self.dependencies()? {
+            if references.is_empty() {
+                dot.push_str(&format!("    {};\n", cell_name(cell)));
+            }
+            for reference in references {
+                dot.push_str(&format!(
+                    "    {} -> {};\n",
+                    cell_name(cell),
+                    cell_name(reference)
+                ));
+            }
+        }
+        dot.push('}');
+        Ok(dot)
+    }
+
+    /// Returns the columns that are not referenced by any formula, with their header names.
+    pub fn unused_columns(&self) -> MinicelResult<Vec<(usize, String)>> {
+        let header: Vec<&str> = self
+            .records
+            .first()
+            .map(|header| header.iter().map(|name| name.trim()).collect())
+            .unwrap_or_default();
+        let mut referenced = vec![false; header.len()];
+        for (row, record) in self.records.iter().enumerate().skip(1) {
+            let line_number = self.line_number(row);
+            for field in record.iter().map(|field| field.trim()) {
+                if !field.starts_with('=') {
+                    continue;
+                }
+                let ast = self.parse_formula(field, row as u64, line_number)?;
+                for (col, _) in ast.function.referenced_fields() {
+                    if let Some(referenced) =
+                        utils::col_number_from_alpha(&col).and_then(|col| referenced.get_mut(col))
+                    {
+                        *referenced = true;
                     }
-                } else if let Expression::Field { col, row, .. } = expr {
-                    log::info!("Child expression is a field Col: {col}, Row: {row}");
+                }
+            }
+        }
+        Ok(header
+            .into_iter()
+            .enumerate()
+            .filter(|(col, _)| !referenced[*col])
+            .map(|(col, name)| (col, name.to_owned()))
+            .collect())
+    }
 
-                    let value =
-                        self.get_field(utils::col_number_from_alpha(col), *row, line_number)?;
-                    *expr = utils::parse_string_to_expression(value);
+    /// Checks the given parsed formula, pushing the found errors to the diagnostics.
+    fn check_formula(
+        &self,
+        formula: &str,
+        function_call: ast::FunctionCallExpression,
+        line_number: usize,
+        diagnostics: &mut Vec<MinicelError>,
+    ) {
+        let engine_error =
+            |message: String| MinicelError::new(MinicelErrorKind::Engine, message, line_number);
+        let mut expressions = vec![Expression::FunctionCall(function_call)];
+        while let Some(expression) = expressions.pop() {
+            match expression {
+                Expression::FunctionCall(function_call) => {
+                    if !builtins::is_builtin(&function_call.name)
+                        && !self.config.allow_unknown_functions
+                    {
+                        diagnostics.push(
+                            engine_error(unknown_function_message(&function_call.name))
+                                .with_code(ErrorCode::UnknownFunction),
+                        );
+                    }
+                    for (index, expected) in builtins::mistyped_arguments(
+                        &function_call.name,
+                        &function_call.arguments,
+                        &self.config,
+                    ) {
+                        diagnostics.push(
+                            engine_error(format!(
+                                "Builtin function error: {} expects {expected}, found `{}`",
+                                function_call.name, function_call.arguments[index]
+                            ))
+                            .with_span(function_call.argument_spans.get(index).cloned())
+                            .with_formula(formula),
+                        );
+                    }
+                    expressions.extend(function_call.arguments);
                 }
+                Expression::Array(array) => expressions.extend(array),
+                range @ Expression::Range { .. } => expressions.extend(range.expand_range()),
+                Expression::Field { col, row, .. } => {
+                    let col_number = match column_number(&col, line_number) {
+                        Ok(col_number) => col_number,
+                        Err(err) => {
+                            diagnostics.push(err);
+                            continue;
+                        }
+                    };
+                    match self.records.get(row as usize) {
+                        Some(record) if row as usize <= self.rows => {
+                            let columns = record.len();
+                            if columns <= col_number {
+                                diagnostics.push(
+                                    engine_error(format!(
+                                        "CSV error: Record {row} has only {columns} columns, cannot get column {col}"
+                                    ))
+                                    .with_code(ErrorCode::InvalidReference),
+                                );
+                            }
+                        }
+                        _ => diagnostics.push(
+                            engine_error(self.invalid_row_message(row as usize))
+                                .with_code(ErrorCode::InvalidReference),
+                        ),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs the given function call of the formula field through the evaluation cache, the key is the
+    /// formula and the values of its referenced cells.
+    ///
+    /// The referenced cells are evaluated once, their values are the key and replace them in the
+    /// function call if it's evaluated.
+    fn cached_function_call(
+        &mut self,
+        field: &str,
+        mut function_call: ast::FunctionCallExpression,
+        line_number: usize,
+    ) -> MinicelResult<Expression> {
+        let referenced_fields = function_call.referenced_fields();
+        let referenced_values = referenced_fields
+            .iter()
+            .map(|(col, row)| self.get_field(column_number(col, line_number)?, *row, line_number))
+            .collect::<MinicelResult<Vec<_>>>();
+        // The referenced cells errors are reported by the evaluation, if they are evaluated
+        let Ok(referenced_values) = referenced_values else {
+            return self.evaluate_function_call(function_call);
+        };
+
+        let key = EvaluationCache::key(field, &referenced_values, &self.config);
+        if let Some(value) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            log::debug!("Found the field \"{field}\" in the cache: {value}");
+            return Ok(Expression::String(value.clone()));
+        }
+        let values = referenced_fields
+            .into_iter()
+            .zip(referenced_values)
+            .collect::<HashMap<_, _>>();
+        substitute_arguments(
+            &mut function_call,
+            &values,
+            self.config.boolean_cells,
+            self.config.decimal_comma,
+        );
+        let value = self.evaluate_function_call(function_call)?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(key, value.to_string());
+        }
+        Ok(value)
+    }
+
+    /// Executes the given field of the given row if it is a function call. A field starting with `'=`
+    /// is escaped, it is the literal text after the `'`.
+    pub fn execute_field(
+        &mut self,
+        field: String,
+        row: u64,
+        line_number: usize,
+    ) -> MinicelResult<String> {
+        self.evaluate_field(field, row, line_number)
+            .map(|value| value.to_string())
+    }
+
+    /// Executes the given field of the given row, returns its typed value, see [`Engine::execute_field`].
+    /// The literal fields are strings.
+    fn evaluate_field(
+        &mut self,
+        field: String,
+        row: u64,
+        line_number: usize,
+    ) -> MinicelResult<Expression> {
+        log::info!("Executing field \"{field}\" at line {line_number}");
+
+        if field.starts_with("'=") {
+            log::info!("Field is an escaped literal");
+            Ok(Expression::String(field[1..].to_owned()))
+        } else if field.starts_with('=') {
+            log::info!("Field is a function call");
+
+            let ast = self.parse_formula(&field, row, line_number)?;
+            if self.config.allow_unknown_functions && ast.function.calls_unknown_function() {
+                log::info!("Field calls an unknown function, keeping it unchanged");
+                return Ok(Expression::String(field));
             }
-            self.function_call(ast.function)
+            let result = if self.cache.is_some() && !ast.function.calls_nondeterministic_function()
+            {
+                self.cached_function_call(&field, ast.function, line_number)
+            } else {
+                self.evaluate_function_call(ast.function)
+            };
+            result.map_err(|err| err.with_formula(&field))
         } else {
             log::info!("Field is not a function call");
-            Ok(field)
+            Ok(Expression::String(field))
+        }
+    }
+
+    /// Executes the given field of the given cell, detecting the circular references.
+    ///
+    /// With `allow_self_reference` the cells of a cycle are evaluated iteratively, a reference back
+    /// to a cell that is being evaluated is its value of the previous iteration (`0` at first),
+    /// until the value converges within `iteration_tolerance` or `max_iterations` is reached.
+    fn execute_cell(
+        &mut self,
+        col: usize,
+        row: u64,
+        field: String,
+        line_number: usize,
+    ) -> MinicelResult<Expression> {
+        let cell = (col, row);
+        if let Some(idx) = self.evaluating.iter().position(|c| c == &cell) {
+            if !self.config.allow_self_reference {
+                let cycle = self.evaluating[idx..]
+                    .iter()
+                    .chain([&cell])
+                    .map(|(col, row)| format!("{}{row}", utils::col_alpha_from_number(*col)))
+                    .collect::<Vec<_>>();
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!(
+                        "Circular reference {}, allow it with `--allow-self-reference`",
+                        cycle.join(" -> ")
+                    ),
+                    line_number,
+                ));
+            }
+            let previous = self
+                .iterated_cells
+                .entry(cell)
+                .or_insert_with(|| "0".to_owned());
+            log::debug!("Returning the previous iteration of the cell {cell:?}: {previous}");
+            return Ok(Expression::String(previous.clone()));
+        }
+
+        self.evaluating.push(cell);
+        let mut result = self.evaluate_field(field.clone(), row, line_number);
+        let mut iterations = 1;
+        while let (Ok(value), Some(previous)) = (&result, self.iterated_cells.get(&cell)) {
+            let value = value.to_string();
+            if iterations > 1 && self.converged(previous, &value) {
+                break;
+            }
+            if iterations >= self.config.max_iterations {
+                log::warn!(
+                    "The cell {}{row} did not converge after {iterations} iterations",
+                    utils::col_alpha_from_number(col)
+                );
+                break;
+            }
+            self.iterated_cells.insert(cell, value);
+            result = self.evaluate_field(field.clone(), row, line_number);
+            iterations += 1;
+        }
+        self.iterated_cells.remove(&cell);
+        self.evaluating.pop();
+        result
+    }
+
+    /// Returns whether the given iteration value converged, the numbers within `iteration_tolerance`
+    /// of the previous iteration, and the other values equal to it.
+    fn converged(&self, previous: &str, value: &str) -> bool {
+        match (utils::parse_number(previous), utils::parse_number(value)) {
+            (Some(previous), Some(value)) => {
+                (value - previous).abs() <= self.config.iteration_tolerance
+            }
+            _ => previous == value,
+        }
+    }
+
+    /// Returns the error message of a reference to the given row that is beyond the sheet.
+    fn invalid_row_message(&self, row: usize) -> String {
+        if self.rows == 0 {
+            format!("Invalid row number {row}, the sheet has only the header and no data rows")
+        } else {
+            format!("Invalid row number {row}, the rows is {}", self.rows)
         }
     }
 
@@ -147,16 +1101,17 @@ impl<'a> Engine<'a> {
         if row > self.rows {
             return Err(MinicelError::new(
                 MinicelErrorKind::Engine,
-                format!("Invalid row number {row}, the rows is {}", self.rows),
+                self.invalid_row_message(row),
                 row + 1,
-            ));
+            )
+            .with_code(ErrorCode::InvalidReference));
         }
 
         let record = self
-            .lines
+            .records
             .get(row)
             .unwrap()
-            .split(',')
+            .iter()
             .map(|f| f.trim().to_owned())
             .collect::<Vec<String>>();
         log::debug!("Read record: {record:?} successfully");
@@ -165,49 +1120,236 @@ impl<'a> Engine<'a> {
 
     /// Returns the field value by column and row.
     pub fn get_field(&mut self, col: usize, row: u64, line_number: usize) -> MinicelResult<String> {
+        self.get_value(col, row, line_number)
+            .map(|value| value.to_string())
+    }
+
+    /// Returns the typed field value by column and row, the already evaluated fields are strings.
+    fn get_value(&mut self, col: usize, row: u64, line_number: usize) -> MinicelResult<Expression> {
         log::info!("Getting field Col: {col}, Row: {row}");
+        if self.is_header_row(row as usize) {
+            return Err(header_reference_error(line_number));
+        }
+        if let Some(err) = self.failed_cells.get(&(col, row)) {
+            log::debug!("The field failed, returning its error: {err}");
+            return Err(err.clone());
+        }
 
-        let str_value =
-            if let Some(updated_field) = self.updated_records.iter().find(|f| f.0 == row) {
-                log::debug!(
-                    "Found the record as an updated record: {:?}",
-                    updated_field.1
-                );
-                if updated_field.1.len() < col {
-                    return Err(MinicelError::new(
-                        MinicelErrorKind::Engine,
-                        format!(
-                            "CSV error: Record {row} has only {} columns, cannot get column {col}",
-                            updated_field.1.len(),
-                        ),
-                        line_number,
-                    ));
-                }
-                log::debug!("Returning the updated field: {}", updated_field.1[col]);
-                updated_field.1[col].clone()
-            } else {
-                log::info!("Getting the record from the CSV file");
-                let field_line_number = (row + 1) as usize;
+        let current_field = self
+            .current_record
+            .as_ref()
+            .filter(|(r, _)| r == &row)
+            .and_then(|(_, fields)| fields.get(col));
+        let value = if let Some(current_field) = current_field {
+            log::debug!("Returning the already evaluated field: {current_field}");
+            Expression::String(current_field.clone())
+        } else if let Some(updated_record) = self.updated_records.get(&row) {
+            log::debug!("Found the record as an updated record: {updated_record:?}");
+            if updated_record.len() <= col {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!(
+                        "CSV error: Record {row} has only {} columns, cannot get column {col}",
+                        updated_record.len(),
+                    ),
+                    line_number,
+                )
+                .with_code(ErrorCode::InvalidReference));
+            }
+            log::debug!("Returning the updated field: {}", updated_record[col]);
+            Expression::String(updated_record[col].clone())
+        } else {
+            log::info!("Getting the record from the CSV file");
+            let field_line_number = self.line_number(row as usize);
 
-                let record = self.get_record(row as usize)?;
-                if record.len() < col {
-                    return Err(MinicelError::new(
-                        MinicelErrorKind::Engine,
-                        format!(
-                            "CSV error: Record {row} has only {} columns, cannot get column {col}",
-                            record.len(),
-                        ),
-                        field_line_number,
-                    ));
+            let record = self.get_record(row as usize)?;
+            if record.len() <= col {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!(
+                        "CSV error: Record {row} has only {} columns, cannot get column {col}",
+                        record.len(),
+                    ),
+                    field_line_number,
+                )
+                .with_code(ErrorCode::InvalidReference));
+            }
+
+            log::debug!("Executing the field: {}", record[col]);
+            let field =
+                self.execute_cell(col, row, record[col].trim().to_owned(), field_line_number)?;
+            log::debug!("Returning the field: {}", field);
+            field
+        };
+
+        Ok(value)
+    }
+
+    /// Writes the evaluation of the given formula cell to the trace file as a JSON line, if there is one.
+    /// e.g. `{"cell":"B1","row":1,"line":2,"formula":"=sum(A1;1)","result":"2"}`, a failed evaluation
+    /// has the `error` message and its `kind` instead of the `result`.
+    fn trace_cell(
+        &mut self,
+        col: usize,
+        row: usize,
+        formula: &str,
+        result: &MinicelResult<String>,
+    ) -> MinicelResult<()> {
+        let line_number = self.line_number(row);
+        let Some(trace) = self.trace.as_mut() else {
+            return Ok(());
+        };
+        let mut entry = vec![
+            (
+                "cell".to_owned(),
+                JsonValue::String(format!("{}{row}", utils::col_alpha_from_number(col))),
+            ),
+            ("row".to_owned(), JsonValue::Number(row.to_string())),
+            (
+                "line".to_owned(),
+                JsonValue::Number(line_number.to_string()),
+            ),
+            ("formula".to_owned(), JsonValue::String(formula.to_owned())),
+        ];
+        match result {
+            Ok(value) => entry.push(("result".to_owned(), JsonValue::String(value.clone()))),
+            Err(err) => {
+                entry.push(("error".to_owned(), JsonValue::String(err.message.clone())));
+                entry.push((
+                    "kind".to_owned(),
+                    JsonValue::String(err.kind.as_str().to_owned()),
+                ));
+            }
+        }
+        writeln!(trace, "{}", JsonValue::Object(entry))
+            // Flushed on the errors, the engine may stop on them
+            .and_then(|_| {
+                if result.is_err() {
+                    trace.flush()
+                } else {
+                    Ok(())
                 }
+            })
+            .map_err(|err| {
+                MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("Write trace file error `{}`", err),
+                    line_number,
+                )
+            })
+    }
 
-                log::debug!("Executing the field: {}", record[col]);
-                let field = self.execute_field(record[col].trim().to_owned(), field_line_number)?;
-                log::debug!("Returning the field: {}", field);
-                field
-            };
+    /// Returns a remediation hint of the given evaluation error, if there is one.
+    pub fn explain_error(&self, error: &MinicelError) -> Option<String> {
+        match error.code {
+            ErrorCode::DivisionByZero => Some(
+                "The divisor is zero, wrap the formula with `iferror` to use a fallback value"
+                    .to_owned(),
+            ),
+            // The closest builtin function name is in the error message
+            ErrorCode::UnknownFunction => {
+                Some("Run with `--list-builtins` to list the builtin functions".to_owned())
+            }
+            ErrorCode::InvalidReference => {
+                let columns = self
+                    .records
+                    .first()
+                    .map(|header| header.len())
+                    .unwrap_or_default();
+                Some(format!(
+                    "The sheet has {} data rows (1 to {}) and {columns} columns (A to {}), the header can't be referenced",
+                    self.rows,
+                    self.rows,
+                    utils::col_alpha_from_number(columns.saturating_sub(1))
+                ))
+            }
+            ErrorCode::InvalidFormula => Some(
+                "A formula is a function call, e.g. `=sum(A1;2)`, the arguments are separated by `;`"
+                    .to_owned(),
+            ),
+            ErrorCode::InvalidValue
+                if error.message.contains("Expected a number, found the string")
+                    && !self.config.coerce_text_numbers =>
+            {
+                Some("Use `--coerce-text-numbers` to accept the numeric strings as numbers".to_owned())
+            }
+            ErrorCode::InvalidValue => None,
+        }
+    }
+
+    /// Returns the evaluated value of the given cell, the column starts from 0 and the row from 1.
+    pub fn evaluate_cell(&mut self, col: usize, row: u64) -> MinicelResult<String> {
+        self.get_field(col, row, self.line_number(row as usize))
+    }
+
+    /// Returns the typed evaluated value of the given cell, the column starts from 0 and the row from 1.
+    /// The evaluation errors are [`Value::Error`] values.
+    pub fn evaluate_cell_typed(&mut self, col: usize, row: u64) -> Value {
+        match self.get_value(col, row, self.line_number(row as usize)) {
+            Ok(result) => {
+                Value::from_result(result, self.config.boolean_cells, self.config.decimal_comma)
+            }
+            Err(err) => Value::Error(err.code),
+        }
+    }
+
+    /// Sets the raw value (a formula or a literal) of the given cell (e.g. `C3`) before the run,
+    /// the evaluated records of the cell and of the cells depending on it are invalidated.
+    pub fn set_cell(&mut self, reference: &str, value: String) -> MinicelResult<()> {
+        let Some((col, row)) = utils::parse_cell_reference(reference) else {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!("Invalid cell reference `{reference}`, expected a cell (e.g. `C3`)"),
+                0,
+            ));
+        };
+        if self.is_header_row(row as usize) {
+            return Err(header_reference_error(0));
+        }
+        if row as usize > self.rows {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                self.invalid_row_message(row as usize),
+                0,
+            )
+            .with_code(ErrorCode::InvalidReference));
+        }
+        let line_number = self.line_number(row as usize);
+        let record = &mut self.records[row as usize];
+        let Some(field) = record.get_mut(col) else {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "CSV error: Record {row} has only {} columns, cannot update column {}",
+                    record.len(),
+                    col + 1
+                ),
+                line_number,
+            ));
+        };
+        log::debug!("Setting the cell {reference} to: {value}");
+        *field = value;
 
-        Ok(str_value)
+        // The cells depending on the updated cell, directly or through other cells
+        let mut invalidated = vec![(col, row)];
+        match self.dependencies() {
+            Ok(dependencies) => {
+                let mut idx = 0;
+                while let Some(cell) = invalidated.get(idx).copied() {
+                    for (dependent, references) in &dependencies {
+                        if references.contains(&cell) && !invalidated.contains(dependent) {
+                            invalidated.push(*dependent);
+                        }
+                    }
+                    idx += 1;
+                }
+                self.updated_records
+                    .retain(|row, _| !invalidated.iter().any(|(_, r)| r == row));
+            }
+            // The graph can't be built (e.g. an invalid formula), all the records are invalidated
+            Err(_) => self.updated_records.clear(),
+        }
+        Ok(())
     }
 
     /// Update the given field value
@@ -237,78 +1379,1720 @@ impl<'a> Engine<'a> {
         new_record[col] = value;
 
         // If the record is already updated, update the updated record.
-        if let Some(old_idx) = self.updated_records.iter().position(|(r, _)| r == &row) {
-            let (_, old_record) = self.updated_records.remove(old_idx);
-            self.updated_records.push((
-                row,
-                utils::compare_records(static_record, old_record, new_record),
-            ));
-        } else {
-            self.updated_records.push((row, new_record));
-        }
+        let record = match self.updated_records.remove(&row) {
+            Some(old_record) => utils::compare_records(static_record, old_record, new_record),
+            None => new_record,
+        };
+        self.updated_records.insert(row, record);
 
         Ok(())
     }
 
-    /// Runs the engine.
+    /// Returns the duplicate columns, the columns that have the same header name as an earlier column
+    /// or the same contents as an earlier column.
+    pub fn duplicate_columns(&self) -> Vec<usize> {
+        let columns_count = self
+            .records
+            .iter()
+            .map(|record| record.len())
+            .max()
+            .unwrap_or_default();
+        self.duplicate_positions(&(0..columns_count).collect::<Vec<_>>())
+    }
+
+    /// Returns the positions in the given columns of the duplicate columns, the columns that have the
+    /// same header name or the same contents as an earlier column of the given columns.
+    fn duplicate_positions(&self, cols: &[usize]) -> Vec<usize> {
+        // The header name and the data contents of each column
+        let columns = cols
+            .iter()
+            .map(|&col| {
+                let mut fields = self
+                    .records
+                    .iter()
+                    .map(|record| record.get(col).map(|field| field.trim()));
+                (fields.next().flatten(), fields.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        (0..columns.len())
+            .filter(|position| {
+                let (header, contents) = &columns[*position];
+                columns[..*position]
+                    .iter()
+                    .any(|(earlier_header, earlier_contents)| {
+                        (header.is_some() && earlier_header == header)
+                            || earlier_contents == contents
+                    })
+            })
+            .collect()
+    }
+
+    /// Evaluates the fields of the given record in one pass, returns the evaluated record and
+    /// the formula cells with their formulas and results, if `only_formulas` or `keep_formulas`
+    /// is set. The dropped columns are not evaluated.
+    ///
+    /// The evaluated fields are visible to the later fields of the same record. The header record
+    /// is never evaluated, its formula looking cells are kept unchanged.
+    fn evaluate_record(
+        &mut self,
+        row: usize,
+        record: &[String],
+        dropped_columns: &[usize],
+    ) -> MinicelResult<(Vec<String>, Vec<[String; 3]>)> {
+        if self.is_header_row(row) {
+            let header = record
+                .iter()
+                .map(|field| field.trim().to_owned())
+                .collect::<Vec<_>>();
+            for (col, field) in header.iter().enumerate() {
+                if field.starts_with('=') {
+                    log::warn!(
+                        "The header cell {} `{field}` looks like a formula, it is kept unchanged",
+                        utils::col_alpha_from_number(col)
+                    );
+                }
+            }
+            return Ok((header, Vec::new()));
+        }
+
+        self.current_record = Some((row as u64, Vec::new()));
+        let mut formula_results = Vec::new();
+        for (col, field) in record.iter().enumerate() {
+            let field = field.trim();
+            let execution_field = if dropped_columns.contains(&col) {
+                field.to_owned()
+            } else {
+                let result = self
+                    .execute_cell(col, row as u64, field.to_owned(), self.line_number(row))
+                    .map(|value| value.to_string());
+                if field.starts_with('=') {
+                    self.trace_cell(col, row, field, &result)?;
+                }
+                let execution_field = match result {
+                    Ok(execution_field) => {
+                        self.failed_cells.remove(&(col, row as u64));
+                        execution_field
+                    }
+                    Err(err) if self.config.format_profile == FormatProfile::Excel => {
+                        log::warn!("Writing the error code of the field {field}: {err}");
+                        let code = err.code.as_str().to_owned();
+                        self.failed_cells.insert((col, row as u64), err);
+                        code
+                    }
+                    Err(err) => {
+                        self.current_record = None;
+                        return Err(err);
+                    }
+                };
+                if (self.config.only_formulas || self.config.keep_formulas.is_some())
+                    && field.starts_with('=')
+                {
+                    formula_results.push([
+                        format!("{}{row}", utils::col_alpha_from_number(col)),
+                        field.to_owned(),
+                        execution_field.clone(),
+                    ]);
+                }
+                execution_field
+            };
+            if let Some((_, fields)) = self.current_record.as_mut() {
+                fields.push(execution_field);
+            }
+        }
+
+        let (_, evaluated_record) = self
+            .current_record
+            .take()
+            .expect("The current record is set above");
+        // Replaces the record of an earlier evaluation, e.g. of `detect_types` before the run
+        self.updated_records
+            .insert(row as u64, evaluated_record.clone());
+        Ok((evaluated_record, formula_results))
+    }
+
+    /// Evaluates the sheet and returns the header name and the inferred type of each column,
+    /// the empty cells are ignored.
+    pub fn detect_types(&mut self) -> MinicelResult<Vec<(String, ColumnType)>> {
+        let header: Vec<String> = self
+            .records
+            .first()
+            .map(|header| header.iter().map(|name| name.trim().to_owned()).collect())
+            .unwrap_or_default();
+        let mut types = vec![ColumnType::Empty; header.len()];
+
+        for (row, record) in self.records.clone().iter().enumerate().skip(1) {
+            if record.is_empty() {
+                continue;
+            }
+            let (evaluated_record, _) = self.evaluate_record(row, record, &[])?;
+            if types.len() < evaluated_record.len() {
+                types.resize(evaluated_record.len(), ColumnType::Empty);
+            }
+            for (col, value) in evaluated_record.iter().enumerate() {
+                if value.is_empty() {
+                    continue;
+                }
+                let value_type =
+                    ColumnType::of(value, self.config.boolean_cells, self.config.decimal_comma);
+                types[col] = match types[col] {
+                    ColumnType::Empty => value_type,
+                    column_type if column_type == value_type => column_type,
+                    _ => ColumnType::Mixed,
+                };
+            }
+        }
+
+        Ok(types
+            .into_iter()
+            .enumerate()
+            .map(|(col, column_type)| {
+                let name = header
+                    .get(col)
+                    .cloned()
+                    .unwrap_or_else(|| utils::col_alpha_from_number(col));
+                (name, column_type)
+            })
+            .collect())
+    }
+
+    /// Runs the engine, writing the output to the given file.
     pub fn run(&mut self, out_file: &Path) -> MinicelResult<()> {
-        let mut writer = csv::Writer::from_path(out_file).map_err(|err| {
+        let file = File::create(out_file).map_err(|err| {
             MinicelError::new(
                 MinicelErrorKind::Engine,
                 format!("Write CSV file error `{}`", err),
                 0,
             )
         })?;
+        if self.config.tee {
+            self.run_to_writer(utils::TeeWriter::new(file, std::io::stdout()))
+        } else {
+            self.run_to_writer(file)
+        }
+    }
+
+    /// Returns an iterator of the evaluated records, starting with the header. The records are
+    /// evaluated lazily when they are yielded, the empty lines are skipped.
+    ///
+    /// The records are the evaluated ones, before the output options (e.g. `columns` and
+    /// `null_value`) are applied.
+    pub fn evaluated_rows(&mut self) -> EvaluatedRows<'_, 'a> {
+        EvaluatedRows {
+            engine: self,
+            row: 0,
+        }
+    }
 
-        for (row, record) in self.lines.clone().iter().enumerate() {
+    /// Runs the engine, returning the output as a string.
+    pub fn run_to_string(&mut self) -> MinicelResult<String> {
+        let mut output = Vec::new();
+        self.run_to_writer(&mut output)?;
+        String::from_utf8(output).map_err(|_| {
+            MinicelError::new(
+                MinicelErrorKind::Engine,
+                format!(
+                    "The {} output is not a valid UTF-8 string",
+                    self.config.output_encoding.name()
+                ),
+                0,
+            )
+        })
+    }
+
+    /// Runs the engine, writing the output to the given writer.
+    pub fn run_to_writer(&mut self, output: impl Write) -> MinicelResult<()> {
+        log::info!(
+            "Running the engine on {} with output encoding {}",
+            self.file.display(),
+            self.config.output_encoding.name()
+        );
+
+        let mut output =
+            utils::LimitedWriter::new(BufWriter::new(output), self.config.max_output_size);
+        if self.config.output_bom {
+            if self.config.output_encoding != Encoding::Utf8 {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!(
+                        "The byte order mark can't be written with the {} output encoding",
+                        self.config.output_encoding.name()
+                    ),
+                    0,
+                ));
+            }
+            output
+                .write_all(UTF8_BOM)
+                .map_err(|err| output_error(err, 0))?;
+        }
+
+        let mut output = EncodingWriter::new(
+            utils::TrailingNewlineWriter::new(output, !self.config.trailing_newline),
+            self.config.output_encoding,
+            self.config.unmappable_chars,
+        );
+        if !self.config.drop_preamble && !self.config.only_formulas {
+            for (idx, line) in self.preamble.iter().enumerate() {
+                writeln!(output, "{line}").map_err(|err| output_error(err, idx + 1))?;
+            }
+        }
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.config.delimiter as u8)
+            .from_writer(utils::SharedBuffer::default());
+
+        if self.config.only_formulas {
+            write_output_record(&mut writer, &mut output, ["cell", "result"], 0)?;
+        }
+
+        let mut formulas_writer = match self.config.keep_formulas.as_deref() {
+            Some(path) => {
+                let mut formulas_writer = csv::WriterBuilder::new()
+                    .delimiter(self.config.delimiter as u8)
+                    .from_path(path)
+                    .map_err(|err| {
+                        MinicelError::new(
+                            MinicelErrorKind::Engine,
+                            format!("Write formulas CSV file error `{}`", err),
+                            0,
+                        )
+                    })?;
+                formulas_writer
+                    .write_record(["cell", "formula", "result"])
+                    .map_err(|err| {
+                        MinicelError::new(
+                            MinicelErrorKind::Engine,
+                            format!("Write formulas CSV record error `{}`", err),
+                            0,
+                        )
+                    })?;
+                Some(formulas_writer)
+            }
+            None => None,
+        };
+
+        self.trace = match self.config.trace_file.as_deref() {
+            Some(path) => Some(BufWriter::new(File::create(path).map_err(|err| {
+                MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("Write trace file error `{}`", err),
+                    0,
+                )
+            })?)),
+            None => None,
+        };
+
+        // The columns selection is applied before the deduplication, the selected columns are
+        // deduplicated in their selected order.
+        let (dropped_columns, dropped_positions) =
+            match (&self.config.columns, self.config.dedupe_columns) {
+                (_, false) => (Vec::new(), Vec::new()),
+                (Some(columns), true) => (Vec::new(), self.duplicate_positions(columns)),
+                (None, true) => (self.duplicate_columns(), Vec::new()),
+            };
+        log::debug!(
+            "Dropped duplicate columns: {dropped_columns:?}, positions: {dropped_positions:?}"
+        );
+
+        let sampled_rows = self.config.sample.map(|sample| self.sample_rows(sample));
+        log::debug!("Sampled rows: {sampled_rows:?}");
+
+        let mut progress_bar = self
+            .config
+            .progress
+            .then(|| utils::ProgressBar::new(self.records.len()));
+        for (row, record) in self.records.clone().iter().enumerate() {
+            if let Some(progress_bar) = progress_bar.as_mut() {
+                progress_bar.update(row + 1);
+            }
             if record.is_empty() {
                 continue;
             }
-            for (col, field) in record.split(',').enumerate() {
-                let execution_field = self.execute_field(field.trim().to_string(), row + 1)?;
-                if execution_field != field {
-                    self.update_field(col, row as u64, execution_field, row + 1)?;
+            let is_selected = self.is_header_row(row)
+                || sampled_rows.as_ref().map_or_else(
+                    || self.is_only_row(row),
+                    |rows| rows.binary_search(&row).is_ok(),
+                );
+            let (mut output_record, mut formula_results) = if !is_selected {
+                match self.config.other_rows {
+                    OtherRows::Drop => continue,
+                    OtherRows::Copy => (record.clone(), Vec::new()),
                 }
-            }
-            if let Some((_, updated_record)) = self
-                .updated_records
-                .iter()
-                .find(|(r, _)| r == &(row as u64))
-            {
-                writer.write_record(updated_record).map_err(|err| {
-                    MinicelError::new(
-                        MinicelErrorKind::Engine,
-                        format!("Write CSV record error `{}`", err),
-                        row + 1,
-                    )
-                })?;
             } else {
-                writer.write_record(record.split(',')).map_err(|err| {
-                    MinicelError::new(
-                        MinicelErrorKind::Engine,
-                        format!("Write CSV record error `{}`", err),
-                        row + 1,
-                    )
-                })?;
+                match self.evaluate_record(row, record, &dropped_columns) {
+                    Ok(evaluated) => evaluated,
+                    Err(err) if self.config.keep_going_rows => {
+                        log::warn!(
+                            "Keeping the record {row} unchanged because of the error: {err}"
+                        );
+                        (record.clone(), Vec::new())
+                    }
+                    Err(err) => return Err(err),
+                }
+            };
+            if self.config.format_profile == FormatProfile::Excel && is_selected {
+                for (field, raw_field) in output_record.iter_mut().zip(record) {
+                    if raw_field.trim().starts_with('=') {
+                        *field = utils::excel_cell(field);
+                    }
+                }
+            }
+            if self.config.strip_formulas {
+                for field in output_record.iter_mut() {
+                    *field = utils::strip_formula(field);
+                }
+            }
+            for [_, _, result] in formula_results.iter_mut() {
+                if self.config.format_profile == FormatProfile::Excel {
+                    *result = utils::excel_cell(result);
+                }
+                if self.config.strip_formulas {
+                    *result = utils::strip_formula(result);
+                }
+                if result.is_empty() {
+                    *result = self.config.null_value.clone();
+                }
+            }
+            if let Some(formulas_writer) = formulas_writer.as_mut() {
+                for formula_result in &formula_results {
+                    formulas_writer
+                        .write_record(formula_result)
+                        .map_err(|err| {
+                            MinicelError::new(
+                                MinicelErrorKind::Engine,
+                                format!("Write formulas CSV record error `{}`", err),
+                                self.line_number(row),
+                            )
+                        })?;
+                }
+            }
+            if self.config.only_formulas {
+                for [cell, _, result] in formula_results {
+                    write_output_record(
+                        &mut writer,
+                        &mut output,
+                        [cell, result],
+                        self.line_number(row),
+                    )?;
+                }
+                continue;
+            }
+            if let Some(columns) = &self.config.columns {
+                output_record = columns
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| !dropped_positions.contains(position))
+                    .map(|(_, col)| output_record.get(*col).cloned().unwrap_or_default())
+                    .collect();
+            } else if !dropped_columns.is_empty() {
+                output_record = output_record
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(col, _)| !dropped_columns.contains(col))
+                    .map(|(_, field)| field)
+                    .collect();
             }
-            if row % 100 == 0 {
-                writer.flush().map_err(|err| {
+            if let Some(newline_style) = self.config.newline_style {
+                for field in output_record.iter_mut() {
+                    *field = utils::normalize_newlines(field, newline_style);
+                }
+            }
+            if !self.config.null_value.is_empty() {
+                for field in output_record.iter_mut().filter(|field| field.is_empty()) {
+                    *field = self.config.null_value.clone();
+                }
+            }
+            write_output_record(
+                &mut writer,
+                &mut output,
+                &output_record,
+                self.line_number(row),
+            )?;
+            if self.config.flush_interval != 0 && row % self.config.flush_interval == 0 {
+                output.flush().map_err(|err| {
                     MinicelError::new(
                         MinicelErrorKind::Engine,
                         format!("Flush CSV file error `{}`", err),
-                        row + 1,
+                        self.line_number(row),
                     )
                 })?;
             }
         }
-        writer.flush().map_err(|err| {
+        output.flush().map_err(|err| {
             MinicelError::new(
                 MinicelErrorKind::Engine,
                 format!("Flush CSV file error `{}`", err),
                 0,
             )
         })?;
+        if let Some(formulas_writer) = formulas_writer.as_mut() {
+            formulas_writer.flush().map_err(|err| {
+                MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("Flush formulas CSV file error `{}`", err),
+                    0,
+                )
+            })?;
+        }
+        if let Some(mut trace) = self.trace.take() {
+            trace.flush().map_err(|err| {
+                MinicelError::new(
+                    MinicelErrorKind::Engine,
+                    format!("Flush trace file error `{}`", err),
+                    0,
+                )
+            })?;
+        }
+        if let Some(progress_bar) = progress_bar.as_mut() {
+            progress_bar.finish();
+        }
+        if let (Some(cache), Some(cache_file)) = (&self.cache, &self.config.cache_file) {
+            cache
+                .save(cache_file)
+                .map_err(|err| MinicelError::new(MinicelErrorKind::Engine, err, 0))?;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NewlineStyle;
+
+    /// Runs the engine on the given sheet with the given configuration, returns the output.
+    fn run_with(csv: &str, config: EngineConfig) -> Result<String, String> {
+        Engine::new(PathBuf::from("test.csv"), csv, config)
+            .and_then(|mut engine| engine.run_to_string())
+            .map_err(|err| err.message)
+    }
+
+    /// Runs the engine on the given sheet with the default configuration, returns the output.
+    fn run(csv: &str) -> Result<String, String> {
+        run_with(csv, EngineConfig::default())
+    }
+
+    #[test]
+    fn dedupes_the_selected_columns_in_their_order() {
+        let config = EngineConfig {
+            dedupe_columns: true,
+            columns: Some(vec![3, 0]),
+            ..EngineConfig::default()
+        };
+        assert_eq!(run_with("a,b,c,a\n1,2,3,4\n", config).unwrap(), "a\n4\n");
+        let config = EngineConfig {
+            dedupe_columns: true,
+            columns: Some(vec![3, 1]),
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,c,a\n1,2,3,4\n", config).unwrap(),
+            "a,b\n4,2\n"
+        );
+    }
+
+    #[test]
+    fn dedupes_the_columns_without_a_selection() {
+        let config = EngineConfig {
+            dedupe_columns: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,c,a\n1,2,2,4\n", config).unwrap(),
+            "a,b\n1,2\n"
+        );
+    }
+
+    #[test]
+    fn reuses_the_cached_results_of_the_same_options_only() {
+        let path = std::env::temp_dir().join(format!(
+            "minicel-engine-cache-test-{}.json",
+            std::process::id()
+        ));
+        let config = |rounding_mode| EngineConfig {
+            cache_file: Some(path.clone()),
+            rounding_mode,
+            ..EngineConfig::default()
+        };
+        let sheet = "a,b\n1,=round(2.5)\n";
+        assert_eq!(
+            run_with(sheet, config(bigdecimal::RoundingMode::HalfUp)).unwrap(),
+            "a,b\n1,3\n"
+        );
+        // A second run reads the result from the cache instead of evaluating the formula
+        let cache = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, cache.replace(":\"3\"", ":\"cached\"")).unwrap();
+        assert_eq!(
+            run_with(sheet, config(bigdecimal::RoundingMode::HalfUp)).unwrap(),
+            "a,b\n1,cached\n"
+        );
+        assert_eq!(
+            run_with(sheet, config(bigdecimal::RoundingMode::HalfEven)).unwrap(),
+            "a,b\n1,2\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn evaluates_the_referenced_fields_of_a_cached_formula_once() {
+        let path = std::env::temp_dir().join(format!(
+            "minicel-engine-cache-once-test-{}.json",
+            std::process::id()
+        ));
+        // The shuffle advances the seeded generator at each evaluation, a second evaluation of `B2`
+        // by `B1` would differ from the output without the cache
+        let sheet = "a,b\n1,=concat(B2)\n2,=shuffle([1;2;3;4;5;6;7;8;9])\n";
+        let config = |cache_file| EngineConfig {
+            seed: Some(7),
+            cache_file,
+            ..EngineConfig::default()
+        };
+        let uncached = run_with(sheet, config(None)).unwrap();
+        let cached = run_with(sheet, config(Some(path.clone()))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cached, uncached);
+    }
+
+    /// The error logs of the tests, the logger is set once for the tests process.
+    static ERROR_LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    /// A logger keeping the error logs in [`ERROR_LOGS`].
+    struct ErrorLogger;
+
+    impl log::Log for ErrorLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() == log::Level::Error
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                ERROR_LOGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn iferror_catches_the_errors_without_error_logs() {
+        if log::set_logger(&ErrorLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Error);
+        }
+        assert_eq!(
+            run("a,b\n1,=iferror(div(1;0);0)\n2,=iferror(sum(A2;1);fallback())\n3,=iferror(nope();\"x\")\n")
+                .unwrap(),
+            "a,b\n1,0\n2,3\n3,x\n"
+        );
+        let logs = ERROR_LOGS.lock().unwrap();
+        assert!(
+            !logs
+                .iter()
+                .any(|log| log.contains("Division by zero") || log.contains("nope")),
+            "{logs:?}"
+        );
+    }
+
+    #[test]
+    fn iferror_keeps_the_uncaught_errors() {
+        assert!(run("a,b\n1,=sum(iferror(div(1;0);0);div(1;0))\n")
+            .unwrap_err()
+            .contains("Division by zero"));
+        assert!(run("a,b\n1,=iferror(1)\n").is_err());
+    }
+
+    #[test]
+    fn sums_a_range() {
+        assert_eq!(
+            run("a,b\n1,=sum(A1:A3)\n2,=sum(A3:A1)\n3,=sum(A1:A2;[A3])\n").unwrap(),
+            "a,b\n1,6\n2,6\n3,6\n"
+        );
+    }
+
+    #[test]
+    fn expands_a_range_in_the_rows_order() {
+        assert_eq!(
+            run("a,b,c\n1,2,=concat(B2:A1)\n3,4,x\n").unwrap(),
+            "a,b,c\n1,2,1234\n3,4,x\n"
+        );
+    }
+
+    #[test]
+    fn counts_the_range_fields_as_dependencies() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,=sum(A1:A2)\n2,x\n",
+            EngineConfig::default(),
+        )
+        .map_err(|err| err.message)
+        .unwrap();
+        let dependencies = engine.dependencies().map_err(|err| err.message).unwrap();
+        assert_eq!(dependencies, vec![((1, 1), vec![(0, 1), (0, 2)])]);
+    }
+
+    /// Runs the engine on the given sheet with the given configuration, returns its error and line number.
+    fn run_error(csv: &str, config: EngineConfig) -> (String, usize) {
+        let err = Engine::new(PathBuf::from("test.csv"), csv, config)
+            .and_then(|mut engine| engine.run_to_string())
+            .expect_err("the run fails");
+        (err.message, err.line_number)
+    }
+
+    #[test]
+    fn reports_the_unencodable_character_at_its_line() {
+        let config = EngineConfig {
+            output_encoding: Encoding::Latin1,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_error("a,b\n1,x\n€,y\n3,z\n", config),
+            (
+                "The character `€` cannot be encoded in ISO-8859-1".to_owned(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn reports_the_maximum_output_size_at_its_line() {
+        let config = EngineConfig {
+            max_output_size: 8,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_error("a,b\n1,2\n3,4\n", config),
+            (
+                "The output exceeds the maximum output size of 8 bytes".to_owned(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn reads_the_decimal_comma_numbers() {
+        let config = EngineConfig {
+            delimiter: ';',
+            arg_separator: '|',
+            decimal_comma: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a;b\n1,5;=sum(A1|A2)\n2;=sum(A1|A1)\n", config).unwrap(),
+            "a;b\n1,5;3.5\n2;3.0\n"
+        );
+    }
+
+    #[test]
+    fn rejects_the_decimal_comma_with_the_comma_delimiter() {
+        let config = EngineConfig {
+            decimal_comma: true,
+            ..EngineConfig::default()
+        };
+        assert!(run_with("a,b\n1,2\n", config)
+            .unwrap_err()
+            .contains("decimal comma"));
+    }
+
+    #[test]
+    fn sums_the_open_ended_ranges() {
+        assert_eq!(
+            run("a,b\n1,=sum(A2:A)\n2,=sum(A:A2)\n3,x\n").unwrap(),
+            "a,b\n1,5\n2,3\n3,x\n"
+        );
+    }
+
+    #[test]
+    fn skips_the_error_cells_of_a_range_with_skip_errors() {
+        let sheet = "a,b\n1,=sum(A1:A3)\n=div(1;0),=max(A1:A3)\n3,=concat(A2)\n";
+        let config = || EngineConfig {
+            format_profile: FormatProfile::Excel,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(
+                sheet,
+                EngineConfig {
+                    skip_errors: true,
+                    ..config()
+                }
+            )
+            .unwrap(),
+            "a,b\n1,4\n#DIV/0!,3\n3,#DIV/0!\n"
+        );
+        assert_eq!(
+            run_with(sheet, config()).unwrap(),
+            "a,b\n1,#DIV/0!\n#DIV/0!,#DIV/0!\n3,#DIV/0!\n"
+        );
+    }
+
+    #[test]
+    fn propagates_the_error_cells_of_a_range() {
+        assert!(run("a,b\n1,=sum(A1:A2)\n=div(1;0),x\n")
+            .unwrap_err()
+            .contains("Division by zero"));
+    }
+
+    #[test]
+    fn strips_all_the_formulas() {
+        let config = || EngineConfig {
+            strip_formulas: true,
+            allow_unknown_functions: true,
+            ..EngineConfig::default()
+        };
+        let output = run_with(
+            "=a,b,c\n1,=sum(A1;1),'=x\n2,=concat(\"=\";A2),=vlookup(A1)\n",
+            config(),
+        )
+        .unwrap();
+        assert_eq!(output, "'=a,b,c\n1,2,'=x\n2,'=2,'=vlookup(A1)\n");
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(output.as_bytes());
+        for record in reader.records() {
+            for field in record.unwrap().iter() {
+                assert!(!field.trim_start().starts_with('='), "{field}");
+            }
+        }
+    }
+
+    #[test]
+    fn writes_the_latin1_output() {
+        let config = EngineConfig {
+            output_encoding: Encoding::Latin1,
+            ..EngineConfig::default()
+        };
+        let mut output = Vec::new();
+        Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\né,=concat(A1;\"è\")\n",
+            config,
+        )
+        .and_then(|mut engine| engine.run_to_writer(&mut output))
+        .map_err(|err| err.message)
+        .unwrap();
+        assert_eq!(output, b"a,b\n\xe9,\xe9\xe8\n");
+    }
+
+    #[test]
+    fn runs_with_the_progress_bar() {
+        let config = EngineConfig {
+            progress: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b\n1,=sum(A1;1)\n2,x\n", config).unwrap(),
+            "a,b\n1,2\n2,x\n"
+        );
+    }
+
+    #[test]
+    fn normalizes_the_newlines_of_the_cells() {
+        let sheet = "a,b\n\"x\r\ny\",\"=concat(A1;\"\"\nz\"\")\"\n\"1\r2\",\"3\n4\"\n";
+        let run_style = |newline_style| {
+            run_with(
+                sheet,
+                EngineConfig {
+                    newline_style,
+                    ..EngineConfig::default()
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            run_style(Some(NewlineStyle::Lf)),
+            "a,b\n\"x\ny\",\"x\ny\nz\"\n\"1\n2\",\"3\n4\"\n"
+        );
+        assert_eq!(
+            run_style(Some(NewlineStyle::CrLf)),
+            "a,b\n\"x\r\ny\",\"x\r\ny\r\nz\"\n\"1\r\n2\",\"3\r\n4\"\n"
+        );
+        assert_eq!(
+            run_style(None),
+            "a,b\n\"x\r\ny\",\"x\r\ny\nz\"\n\"1\r2\",\"3\n4\"\n"
+        );
+    }
+
+    #[test]
+    fn writes_only_the_formula_cells() {
+        let config = EngineConfig {
+            only_formulas: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,c\n1,=sum(A1;1),x\n2,y,=concat(A2;B2)\n3,z,w\n", config).unwrap(),
+            "cell,result\nB1,2\nC2,2y\n"
+        );
+    }
+
+    #[test]
+    fn rejects_an_oversized_line() {
+        let config = || EngineConfig {
+            max_line_length: 8,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_error("a,b\n1,2\n123456789,3\n", config()),
+            (
+                "CSV error: The line is 11 bytes long, the maximum line length is 8 bytes"
+                    .to_owned(),
+                3
+            )
+        );
+        assert_eq!(
+            run_with("a,b\n12345,78\n", config()).unwrap(),
+            "a,b\n12345,78\n"
+        );
+    }
+
+    /// A writer that counts its flushes.
+    #[derive(Default)]
+    struct FlushCounter {
+        written: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flushes_the_output_every_flush_interval() {
+        let sheet = "a,b\n1,=sum(A1;1)\n2,x\n3,y\n4,z\n5,w\n";
+        let run_interval = |flush_interval| {
+            let config = EngineConfig {
+                flush_interval,
+                ..EngineConfig::default()
+            };
+            let mut output = FlushCounter::default();
+            Engine::new(PathBuf::from("test.csv"), sheet, config)
+                .and_then(|mut engine| engine.run_to_writer(&mut output))
+                .map_err(|err| err.message)
+                .unwrap();
+            (String::from_utf8(output.written).unwrap(), output.flushes)
+        };
+        let expected = "a,b\n1,2\n2,x\n3,y\n4,z\n5,w\n";
+        // The header is the row 0, a multiple of every interval
+        for (flush_interval, flushes) in [(0, 1), (1, 7), (2, 4), (5, 3), (100, 2)] {
+            assert_eq!(
+                run_interval(flush_interval),
+                (expected.to_owned(), flushes),
+                "{flush_interval}"
+            );
+        }
+    }
+
+    #[test]
+    fn doesnt_evaluate_the_duplicate_columns() {
+        let config = EngineConfig {
+            dedupe_columns: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,a\n1,=sum(A1;1),=div(1;0)\n", config).unwrap(),
+            "a,b\n1,2\n"
+        );
+    }
+
+    #[test]
+    fn reads_the_boolean_cells() {
+        assert_eq!(
+            run("a,b,c\ntrue,false,=and(A1;not(B1))\n").unwrap(),
+            "a,b,c\ntrue,false,true\n"
+        );
+
+        let typed = |boolean_cells| {
+            let config = EngineConfig {
+                boolean_cells,
+                ..EngineConfig::default()
+            };
+            Engine::new(PathBuf::from("test.csv"), "a\ntrue\n", config)
+                .map_err(|err| err.message)
+                .unwrap()
+                .evaluate_cell_typed(0, 1)
+        };
+        assert_eq!(typed(true), Value::Bool(true));
+        assert_eq!(typed(false), Value::Text("true".to_owned()));
+    }
+
+    #[test]
+    fn keeps_the_failing_rows_unchanged_with_keep_going_rows() {
+        let config = EngineConfig {
+            keep_going_rows: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(
+                "a,b,c\n1,=sum(A1;1),x\n2,=sum(A2;1),=div(A2;0)\n3,=sum(A3;1),y\n",
+                config
+            )
+            .unwrap(),
+            "a,b,c\n1,2,x\n2,=sum(A2;1),=div(A2;0)\n3,4,y\n"
+        );
+        assert!(run("a,b\n1,=div(A1;0)\n").is_err());
+    }
+
+    #[test]
+    fn reads_the_comma_separated_formulas_under_a_non_comma_delimiter() {
+        let config = EngineConfig {
+            delimiter: ';',
+            arg_separator: ',',
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a;b;c\n1;2;=sum(A1,B1,3)\nx;y;=concat(A2,B2)\n", config).unwrap(),
+            "a;b;c\n1;2;6\nx;y;xy\n"
+        );
+    }
+
+    #[test]
+    fn evaluates_the_intra_row_references() {
+        assert_eq!(
+            run("a,b,c\n1,=sum(A1;1),=sum(B1;1)\n=sum(C2;1),=sum(A2;1),5\n").unwrap(),
+            "a,b,c\n1,2,3\n6,7,5\n"
+        );
+    }
+
+    #[test]
+    fn runs_to_a_string() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,=sum(A1;2)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.run_to_string().unwrap_or_else(|err| panic!("{err}")),
+            "a,b\n1,3\n"
+        );
+    }
+
+    #[test]
+    fn renders_the_blank_cells_as_the_null_value() {
+        let config = EngineConfig {
+            null_value: "NULL".to_owned(),
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,c\n1,,=concat(B1)\n,x,=concat(A2;B2)\n", config).unwrap(),
+            "a,b,c\n1,NULL,NULL\nNULL,x,x\n"
+        );
+        assert_eq!(run("a,b,c\n1,,=concat(B1)\n").unwrap(), "a,b,c\n1,,\n");
+    }
+
+    #[test]
+    fn keeps_the_formula_looking_header_cells_unchanged() {
+        assert_eq!(
+            run("=sum(A1;1),=b(,c\n1,2,3\n").unwrap(),
+            "=sum(A1;1),=b(,c\n1,2,3\n"
+        );
+    }
+
+    #[test]
+    fn evaluates_the_deeply_nested_function_calls() {
+        let nested = |depth| format!("{}1{}", "sum(".repeat(depth), ")".repeat(depth));
+        assert_eq!(
+            run(&format!("a,b\n1,={}\n", nested(256))).unwrap(),
+            "a,b\n1,1\n"
+        );
+        assert!(run(&format!("a,b\n1,={}\n", nested(5000)))
+            .unwrap_err()
+            .contains("Maximum nesting depth"));
+    }
+
+    #[test]
+    fn writes_the_byte_order_mark_with_output_bom() {
+        let run_to_bytes = |output_bom| {
+            let config = EngineConfig {
+                output_bom,
+                ..EngineConfig::default()
+            };
+            let mut output = Vec::new();
+            Engine::new(PathBuf::from("test.csv"), "a,b\n1,=sum(A1;1)\n", config)
+                .and_then(|mut engine| engine.run_to_writer(&mut output))
+                .unwrap_or_else(|err| panic!("{err}"));
+            output
+        };
+        assert_eq!(run_to_bytes(true), b"\xEF\xBB\xBFa,b\n1,2\n");
+        assert_eq!(run_to_bytes(false), b"a,b\n1,2\n");
+    }
+
+    #[test]
+    fn reports_the_duplicate_header_names_with_strict_header() {
+        let config = || EngineConfig {
+            strict_header: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_error("id,name, id,,name\n1,2,3,4,5\n", config()),
+            (
+                "CSV error: Invalid header, empty names in D; `id` is duplicated in A, C; \
+                 `name` is duplicated in B, E"
+                    .to_owned(),
+                1
+            )
+        );
+        assert!(run_with("id,name\n1,2\n", config()).is_ok());
+        assert!(run("id,id\n1,2\n").is_ok());
+    }
+
+    #[test]
+    fn outputs_the_escaped_formulas_as_literal_text() {
+        assert_eq!(
+            run("a,b,c\n'=hello,'=sum(1;2),=concat(A1;B1)\n").unwrap(),
+            "a,b,c\n=hello,=sum(1;2),=hello=sum(1;2)\n"
+        );
+    }
+
+    #[test]
+    fn detects_the_column_types() {
+        let types = Engine::new(
+            PathBuf::from("test.csv"),
+            "price,name,date,mixed,empty\n1.5,apple,2024-03-15,1,\n=sum(A1;1),pear,,x,\n",
+            EngineConfig::default(),
+        )
+        .and_then(|mut engine| engine.detect_types())
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            types,
+            [
+                ("price".to_owned(), ColumnType::Number),
+                ("name".to_owned(), ColumnType::String),
+                ("date".to_owned(), ColumnType::Date),
+                ("mixed".to_owned(), ColumnType::Mixed),
+                ("empty".to_owned(), ColumnType::Empty),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_one_updated_record_per_row_after_detect_types_and_run() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,=sum(A1;1)\n2,=sum(A2;B1)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        engine.detect_types().unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.run_to_string().unwrap_or_else(|err| panic!("{err}")),
+            "a,b\n1,2\n2,4\n"
+        );
+        let mut rows = engine.updated_records.keys().copied().collect::<Vec<_>>();
+        rows.sort_unstable();
+        assert_eq!(rows, [1, 2]);
+        assert_eq!(engine.updated_records[&2], ["2", "4"]);
+    }
+
+    #[test]
+    fn passes_the_unknown_functions_through_with_allow_unknown_functions() {
+        let sheet = "a,b,c\n1,=vlookup(A1;2),=sum(A1;1)\n";
+        let config = EngineConfig {
+            allow_unknown_functions: true,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(sheet, config).unwrap(),
+            "a,b,c\n1,=vlookup(A1;2),2\n"
+        );
+        assert!(run(sheet).unwrap_err().contains("Unknown function vlookup"));
+    }
+
+    #[test]
+    fn reads_the_header_from_the_given_row() {
+        let sheet = "report,2024\n=note,x\nprice,total\n2,=sum(A1;1)\n3,=sum(A2;B1)\n";
+        let config = |drop_preamble| EngineConfig {
+            header_row: 3,
+            drop_preamble,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(sheet, config(false)).unwrap(),
+            "report,2024\n=note,x\nprice,total\n2,3\n3,6\n"
+        );
+        assert_eq!(
+            run_with(sheet, config(true)).unwrap(),
+            "price,total\n2,3\n3,6\n"
+        );
+    }
+
+    #[test]
+    fn underlines_the_mistyped_argument_of_the_failed_formula() {
+        let snippet = |csv: &str| {
+            Engine::new(PathBuf::from("test.csv"), csv, EngineConfig::default())
+                .and_then(|mut engine| engine.run_to_string())
+                .expect_err("the run fails")
+                .snippet()
+        };
+        let expected = Some("=sum(1; \"x\")\n        ^^^".to_owned());
+        assert_eq!(snippet("a,b\n1,\"=sum(1; \"\"x\"\")\"\n"), expected);
+        // The snippet is the formula of the referenced cell that failed
+        assert_eq!(
+            snippet("a,b\n=sum(B1;1),\"=sum(1; \"\"x\"\")\"\n"),
+            expected
+        );
+        assert_eq!(snippet("a,b\n1,=sum(A1;B3)\n"), None);
+    }
+
+    #[test]
+    fn validate_underlines_the_mistyped_literal_arguments() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,\"=sum(1; \"\"x\"\"; true)\"\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let snippets = engine
+            .validate()
+            .into_iter()
+            .map(|err| err.snippet())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            snippets,
+            [
+                Some("=sum(1; \"x\"; true)\n        ^^^".to_owned()),
+                Some("=sum(1; \"x\"; true)\n             ^^^^".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_checks_the_arguments_of_every_builtin_signature() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n\"=not(\"\"x\"\")\",\"=join(1; \"\",\"\")\"\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine
+                .validate()
+                .into_iter()
+                .map(|err| (err.message.clone(), err.snippet()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "Builtin function error: not expects a boolean, found `x`".to_owned(),
+                    Some("=not(\"x\")\n     ^^^".to_owned())
+                ),
+                (
+                    "Builtin function error: join expects an array, found `1`".to_owned(),
+                    Some("=join(1; \",\")\n      ^".to_owned())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_returns_all_the_diagnostics() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,=sum(A9;1)\n2,=vlookup(A2)\n3,=sum(A3;2)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let diagnostics = engine
+            .validate()
+            .into_iter()
+            .map(|err| (err.message, err.line_number))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                ("Invalid row number 9, the rows is 3".to_owned(), 2),
+                ("Unknown function vlookup".to_owned(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn controls_the_trailing_newline() {
+        let sheet = "a,b\n1,=sum(A1;1)\n2,\"x\ny\"\n";
+        let config = |trailing_newline| EngineConfig {
+            trailing_newline,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(sheet, config(true)).unwrap(),
+            "a,b\n1,2\n2,\"x\ny\"\n"
+        );
+        assert_eq!(
+            run_with(sheet, config(false)).unwrap(),
+            "a,b\n1,2\n2,\"x\ny\""
+        );
+    }
+
+    #[test]
+    fn reports_the_unused_columns() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "price,note,quantity,total\n2,x,3,=sum(A1;C1)\n4,y,5,=sum(A1:A2)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine
+                .unused_columns()
+                .map_err(|err| err.message)
+                .as_deref(),
+            Ok([(1, "note".to_owned()), (3, "total".to_owned())].as_slice())
+        );
+    }
+
+    #[test]
+    fn rejects_the_empty_input() {
+        assert_eq!(
+            run_error("", EngineConfig::default()),
+            (
+                "CSV error: The input is empty, expected at least the header".to_owned(),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn runs_a_header_only_sheet() {
+        assert_eq!(run("a,b\n").unwrap(), "a,b\n");
+
+        let mut engine = Engine::new(PathBuf::from("test.csv"), "a,b\n", EngineConfig::default())
+            .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.evaluate_cell(0, 1).map_err(|err| err.message),
+            Err("Invalid row number 1, the sheet has only the header and no data rows".to_owned())
+        );
+        assert!(engine.validate().is_empty());
+    }
+
+    #[test]
+    fn evaluates_only_the_selected_rows() {
+        let sheet = "a,b\n1,=sum(A1;1)\n2,=sum(B1;1)\n3,=sum(A3;1)\n4,=sum(A4;1)\n";
+        let config = |other_rows| EngineConfig {
+            only_rows: Some(vec![2..=3]),
+            other_rows,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(sheet, config(OtherRows::Drop)).unwrap(),
+            "a,b\n2,3\n3,4\n"
+        );
+        assert_eq!(
+            run_with(sheet, config(OtherRows::Copy)).unwrap(),
+            "a,b\n1,=sum(A1;1)\n2,3\n3,4\n4,=sum(A4;1)\n"
+        );
+    }
+
+    #[test]
+    fn evaluates_the_typed_cells() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b,c,d,e\n=sum(1.5;1),=concat(\"x\";1),=gt(2;1),=div(1;0),\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let values = (0..5)
+            .map(|col| engine.evaluate_cell_typed(col, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                Value::Number(bigdecimal::BigDecimal::new(25.into(), 1)),
+                Value::Text("x1".to_owned()),
+                Value::Bool(true),
+                Value::Error(ErrorCode::DivisionByZero),
+                Value::Empty,
+            ]
+        );
+        assert_eq!(
+            values.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["2.5", "x1", "true", "#DIV/0!", ""]
+        );
+    }
+
+    #[test]
+    fn evaluates_the_typed_arrays_and_error_codes() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b,c\n=shuffle([1;\"x\";true]),=sumn(1),=sum(A5)\n",
+            EngineConfig {
+                seed: Some(0),
+                ..Default::default()
+            },
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let Value::Array(mut elements) = engine.evaluate_cell_typed(0, 1) else {
+            panic!("shuffle returns an array");
+        };
+        elements.sort_by_key(ToString::to_string);
+        assert_eq!(
+            elements,
+            [
+                Value::Number(1.into()),
+                Value::Bool(true),
+                Value::Text("x".to_owned())
+            ]
+        );
+        assert_eq!(
+            engine.evaluate_cell_typed(1, 1),
+            Value::Error(ErrorCode::UnknownFunction)
+        );
+        assert_eq!(
+            engine.evaluate_cell_typed(2, 1),
+            Value::Error(ErrorCode::InvalidReference)
+        );
+    }
+
+    #[test]
+    fn skips_the_header_row_in_the_run() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "=sum(1;1),b\n1,=sum(A1;1)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert!(engine.is_header_row(0));
+        assert!(!engine.is_header_row(1));
+        let rows = engine
+            .evaluated_rows()
+            .map(|record| record.map_err(|err| err.message))
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(
+            rows,
+            Ok(vec![
+                vec!["=sum(1;1)".to_owned(), "b".to_owned()],
+                vec!["1".to_owned(), "2".to_owned()],
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_the_header_row_as_a_reference() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,2\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.evaluate_cell(0, 0).map_err(|err| err.message),
+            Err("Invalid row number 0, the header can't be referenced".to_owned())
+        );
+        assert_eq!(
+            run("a,b\n1,=sum(A0;1)\n"),
+            Err("Invalid field identifier, row number starts from 1, found 0".to_owned())
+        );
+    }
+
+    #[test]
+    fn explains_the_errors_with_hints() {
+        let explain = |csv: &str| {
+            let mut engine = Engine::new(PathBuf::from("test.csv"), csv, EngineConfig::default())
+                .unwrap_or_else(|err| panic!("{err}"));
+            let err = engine.run_to_string().expect_err("the sheet fails");
+            (err.message.clone(), engine.explain_error(&err))
+        };
+        assert_eq!(
+            explain("a,b\n1,=sumn(A1;1)\n"),
+            (
+                "Unknown function sumn, did you mean sum?".to_owned(),
+                Some("Run with `--list-builtins` to list the builtin functions".to_owned())
+            )
+        );
+        assert_eq!(
+            explain("a,b\n1,=sum(A5;1)\n").1.as_deref(),
+            Some(
+                "The sheet has 1 data rows (1 to 1) and 2 columns (A to B), the header can't be referenced"
+            )
+        );
+        assert_eq!(
+            explain("a,b\n1,=sum(A1;\"x\")\n").1.as_deref(),
+            Some("Use `--coerce-text-numbers` to accept the numeric strings as numbers")
+        );
+    }
+
+    #[test]
+    fn evaluated_rows_match_the_string_output() {
+        let sheet = "a,b,c\n1,=sum(A1;1),=concat(B1;\"x\")\n\n2,=sum(A2;B1),\n";
+        let mut engine = Engine::new(PathBuf::from("test.csv"), sheet, EngineConfig::default())
+            .unwrap_or_else(|err| panic!("{err}"));
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for record in engine.evaluated_rows() {
+            writer
+                .write_record(record.unwrap_or_else(|err| panic!("{err}")))
+                .unwrap();
+        }
+        let rows = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(rows, "a,b,c\n1,2,2x\n2,4,\n");
+        assert_eq!(run(sheet).as_deref(), Ok(rows.as_str()));
+    }
+
+    #[test]
+    fn reads_the_tab_and_semicolon_delimited_sheets() {
+        let tab = EngineConfig {
+            delimiter: '\t',
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a\tb\tc\n1\t2\t=sum(A1;B1)\nx,y\tz\t=concat(A2;B2)\n", tab).unwrap(),
+            "a\tb\tc\n1\t2\t3\nx,y\tz\tx,yz\n"
+        );
+        let semicolon = EngineConfig {
+            delimiter: ';',
+            arg_separator: '|',
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(
+                "a;b;c\n1;2;=sum(A1|B1)\n\"x;y\";z;=concat(A2|B2)\n",
+                semicolon
+            )
+            .unwrap(),
+            "a;b;c\n1;2;3\n\"x;y\";z;\"x;yz\"\n"
+        );
+    }
+
+    #[test]
+    fn rejects_the_delimiter_of_the_argument_separator() {
+        let config = EngineConfig {
+            delimiter: ';',
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_error("a;b\n1;2\n", config),
+            (
+                "CSV error: The delimiter `;` is the argument separator of the formulas, set another \
+                 one with `--arg-separator`"
+                    .to_owned(),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn iterates_a_converging_self_reference_to_its_fixpoint() {
+        let config = EngineConfig {
+            allow_self_reference: true,
+            ..EngineConfig::default()
+        };
+        // x = x / 2 + 1 converges to 2, it stops within the tolerance of the previous iteration
+        assert_eq!(
+            run_with("a,b\n=sum(div(A1;2);1),x\n", config).unwrap(),
+            "a,b\n1.9990234375,x\n"
+        );
+        assert_eq!(
+            run_error("a,b\n=sum(div(A1;2);1),x\n", EngineConfig::default()),
+            (
+                "Circular reference A1 -> A1, allow it with `--allow-self-reference`".to_owned(),
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn stops_a_non_converging_self_reference_at_the_iterations_cap() {
+        let config = EngineConfig {
+            allow_self_reference: true,
+            max_iterations: 5,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b\n=sum(A1;1),=sum(B1;2)\n", config).unwrap(),
+            "a,b\n5,10\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_the_quoted_fields() {
+        let sheet = "name,note,joined\n\"Doe, John\",\"say \"\"hi\"\"\",=concat(A1;\"|\";B1)\n\
+                     \"multi\nline\",x,=concat(A2;B2)\n";
+        let output = run(sheet).unwrap();
+        assert_eq!(
+            output,
+            "name,note,joined\n\"Doe, John\",\"say \"\"hi\"\"\",\"Doe, John|say \"\"hi\"\"\"\n\
+             \"multi\nline\",x,\"multi\nlinex\"\n"
+        );
+        let records = csv::Reader::from_reader(output.as_bytes())
+            .records()
+            .map(|record| {
+                record
+                    .unwrap()
+                    .iter()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                ["Doe, John", "say \"hi\"", "Doe, John|say \"hi\""],
+                ["multi\nline", "x", "multi\nlinex"],
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_the_rows_of_the_parsed_records() {
+        assert_eq!(
+            run("a,b\n\"1\n2\",x\n3,=sum(A2;1)\n").unwrap(),
+            "a,b\n\"1\n2\",x\n3,4\n"
+        );
+        assert!(run("a,b\n\"1\n2\",=sum(A3;1)\n")
+            .unwrap_err()
+            .contains("Invalid row number 3, the rows is 1"));
+    }
+
+    #[test]
+    fn exports_the_dependencies_as_dot() {
+        let engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b,c\n1,=sum(A1;A1;2),=concat(A1;B1)\n2,=pi(),=sum(A1:A2)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.dependencies_dot().map_err(|err| err.message),
+            Ok(
+                "digraph dependencies {\n    \"B1\" -> \"A1\";\n    \"C1\" -> \"B1\";\n    \
+                \"C1\" -> \"A1\";\n    \"B2\";\n    \"C2\" -> \"A1\";\n    \"C2\" -> \"A2\";\n}"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn writes_the_formulas_audit_file_with_keep_formulas() {
+        let path = std::env::temp_dir().join(format!(
+            "minicel-engine-keep-formulas-test-{}.csv",
+            std::process::id()
+        ));
+        let config = EngineConfig {
+            keep_formulas: Some(path.clone()),
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with("a,b,c\n1,2,=sum(A1;B1)\n=concat(\"x\";1),y,z\n", config).unwrap(),
+            "a,b,c\n1,2,3\nx1,y,z\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "cell,formula,result\nC1,=sum(A1;B1),3\nA2,\"=concat(\"\"x\"\";1)\",x1\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recomputes_the_dependents_of_a_set_cell() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,=sum(A1;1)\n2,=sum(B1;A2)\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let evaluate = |engine: &mut Engine, col, row| {
+            engine
+                .evaluate_cell(col, row)
+                .unwrap_or_else(|err| panic!("{err}"))
+        };
+        assert_eq!(evaluate(&mut engine, 1, 2), "4");
+
+        engine
+            .set_cell("A1", "10".to_owned())
+            .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(evaluate(&mut engine, 1, 1), "11");
+        assert_eq!(evaluate(&mut engine, 1, 2), "13");
+
+        engine
+            .set_cell("B1", "=concat(A1;A1)".to_owned())
+            .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(
+            engine.run_to_string().map_err(|err| err.message).as_deref(),
+            Ok("a,b\n10,1010\n2,1012\n")
+        );
+    }
+
+    #[test]
+    fn rejects_setting_a_cell_beyond_the_sheet() {
+        let mut engine = Engine::new(
+            PathBuf::from("test.csv"),
+            "a,b\n1,2\n",
+            EngineConfig::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        let set = |engine: &mut Engine, reference| {
+            engine
+                .set_cell(reference, "3".to_owned())
+                .map_err(|err| err.message)
+        };
+        assert_eq!(
+            set(&mut engine, "A2"),
+            Err("Invalid row number 2, the rows is 1".to_owned())
+        );
+        assert_eq!(
+            set(&mut engine, "C1"),
+            Err("CSV error: Record 1 has only 2 columns, cannot update column 3".to_owned())
+        );
+        assert_eq!(
+            set(&mut engine, "A0"),
+            Err("Invalid row number 0, the header can't be referenced".to_owned())
+        );
+        assert!(set(&mut engine, "1A")
+            .unwrap_err()
+            .contains("Invalid cell reference"));
+    }
+
+    #[test]
+    fn samples_a_deterministic_subset_of_the_rows() {
+        let sheet = "a,b\n1,=sum(A1;1)\n2,x\n3,=sum(A3;A1)\n4,y\n5,z\n";
+        let config = |sample| EngineConfig {
+            sample: Some(sample),
+            seed: Some(1),
+            ..EngineConfig::default()
+        };
+        let output = run_with(sheet, config(2)).unwrap();
+        assert_eq!(output, "a,b\n2,x\n3,4\n");
+        assert_eq!(run_with(sheet, config(2)).unwrap(), output);
+        assert_eq!(
+            run_with(sheet, config(10)).unwrap(),
+            "a,b\n1,2\n2,x\n3,4\n4,y\n5,z\n"
+        );
+    }
+
+    #[test]
+    fn traces_one_entry_per_formula_cell() {
+        let path = std::env::temp_dir().join(format!(
+            "minicel-engine-trace-test-{}.jsonl",
+            std::process::id()
+        ));
+        let config = EngineConfig {
+            trace_file: Some(path.clone()),
+            format_profile: FormatProfile::Excel,
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            run_with(
+                "a,b,c\n1,=sum(A1;1),=concat(B1;A1)\n2,=div(1;0),x\n",
+                config
+            )
+            .unwrap(),
+            "a,b,c\n1,2,21\n2,#DIV/0!,x\n"
+        );
+        let trace = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                r#"{"cell":"B1","row":1,"line":2,"formula":"=sum(A1;1)","result":"2"}"#,
+                r#"{"cell":"C1","row":1,"line":2,"formula":"=concat(B1;A1)","result":"21"}"#,
+                r#"{"cell":"B2","row":2,"line":3,"formula":"=div(1;0)","error":"Builtin function error: Division by zero","kind":"EngineError"}"#,
+            ]
+        );
+        for entry in trace.lines() {
+            assert!(JsonValue::parse(entry).is_ok(), "{entry}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
+use crate::{ast::Span, value::ErrorCode};
+
 /// The errors
 #[derive(Debug, PartialEq, Clone)]
 pub enum ErrorKind {
@@ -6,10 +8,17 @@ pub enum ErrorKind {
     Engine,
 }
 
+#[derive(Debug, Clone)]
 pub struct Error {
     pub kind: ErrorKind,
     pub message: String,
     pub line_number: usize,
+    /// The error code of the cell that failed with the error
+    pub code: ErrorCode,
+    /// The span of the error in its formula
+    pub span: Option<Span>,
+    /// The formula of the span
+    pub formula: Option<String>,
 }
 
 impl ErrorKind {
@@ -24,13 +33,68 @@ impl ErrorKind {
 }
 
 impl Error {
+    /// Creates a new error, its code is `#SYNTAX!` for the tokenizer and the parse errors and
+    /// `#VALUE!` for the engine errors, see [`Error::with_code`].
     pub fn new(kind: ErrorKind, message: String, line_number: usize) -> Self {
+        let code = match kind {
+            ErrorKind::Tokenizer | ErrorKind::Parse => ErrorCode::InvalidFormula,
+            ErrorKind::Engine => ErrorCode::InvalidValue,
+        };
         Self {
             kind,
             message,
             line_number,
+            code,
+            span: None,
+            formula: None,
         }
     }
+
+    /// Sets the error code of the cell that failed with the error.
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Sets the span of the error in its formula.
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Sets the formula of the span, if the error has a span and no formula yet. The formula of a
+    /// referenced cell error is kept.
+    pub fn with_formula(mut self, formula: &str) -> Self {
+        if self.span.is_some() && self.formula.is_none() {
+            self.formula = Some(formula.to_owned());
+        }
+        self
+    }
+
+    /// Returns the given formula with its span underlined by carets, `None` if the error has no span
+    /// in the formula. e.g.
+    ///
+    /// ```text
+    /// =sum(1; "x")
+    ///         ^^^
+    /// ```
+    pub fn render_snippet(&self, formula: &str) -> Option<String> {
+        let span = self.span.clone()?;
+        let padding = formula.get(..span.start)?.chars().count();
+        let width = formula.get(span)?.chars().count().max(1);
+        Some(format!(
+            "{formula}\n{}{}",
+            " ".repeat(padding),
+            "^".repeat(width)
+        ))
+    }
+
+    /// Returns the snippet of the error formula, see [`Error::render_snippet`].
+    pub fn snippet(&self) -> Option<String> {
+        self.formula
+            .as_deref()
+            .and_then(|formula| self.render_snippet(formula))
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -46,3 +110,33 @@ impl std::fmt::Display for Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underlines_the_span_of_the_error() {
+        let error = Error::new(ErrorKind::Engine, "error".to_owned(), 2).with_span(Some(8..11));
+        assert_eq!(
+            error.render_snippet("=sum(1; \"x\")").as_deref(),
+            Some("=sum(1; \"x\")\n        ^^^")
+        );
+        assert_eq!(error.render_snippet("=sum(1)"), None);
+        assert_eq!(error.with_span(None).render_snippet("=sum(1; \"x\")"), None);
+    }
+
+    #[test]
+    fn has_the_error_code_of_its_kind_by_default() {
+        let error = |kind| Error::new(kind, "Division by zero".to_owned(), 2).code;
+        assert_eq!(error(ErrorKind::Tokenizer), ErrorCode::InvalidFormula);
+        assert_eq!(error(ErrorKind::Parse), ErrorCode::InvalidFormula);
+        assert_eq!(error(ErrorKind::Engine), ErrorCode::InvalidValue);
+        assert_eq!(
+            Error::new(ErrorKind::Engine, "error".to_owned(), 2)
+                .with_code(ErrorCode::DivisionByZero)
+                .code,
+            ErrorCode::DivisionByZero
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! A minimal JSON parser and serializer, used by the JSON builtin functions.
+
+use std::{iter::Peekable, str::Chars};
+
+/// The maximum nesting depth of the arrays and objects, like the formulas parser.
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// A JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// A number, kept as written to avoid losing precision.
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// An object, the keys are kept in their order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+/// A segment of a JSON path. e.g. `a.b[0]` is `Key("a")`, `Key("b")`, `Index(0)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Skips the whitespaces.
+fn skip_whitespaces(chars: &mut Peekable<Chars<'_>>) {
+    while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Expects the given character.
+fn expect_char(chars: &mut Peekable<Chars<'_>>, expected: char) -> Result<(), String> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(format!("Invalid JSON, expected `{expected}`, found `{c}`")),
+        None => Err(format!("Invalid JSON, expected `{expected}`, found EOF")),
+    }
+}
+
+/// Parses a JSON string, the opening quote is not consumed yet.
+fn parse_string(chars: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+    expect_char(chars, '"')?;
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => match chars.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('/') => string.push('/'),
+                Some('b') => string.push('\u{8}'),
+                Some('f') => string.push('\u{c}'),
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                Some('u') => {
+                    let code = parse_unicode_escape(chars)?;
+                    let c = if (0xD800..0xDC00).contains(&code) {
+                        expect_char(chars, '\\')?;
+                        expect_char(chars, 'u')?;
+                        let low = parse_unicode_escape(chars)?;
+                        (0xDC00..0xE000)
+                            .contains(&low)
+                            .then(|| 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00))
+                            .and_then(char::from_u32)
+                    } else {
+                        char::from_u32(code)
+                    };
+                    string.push(c.ok_or("Invalid JSON, invalid unicode escape")?);
+                }
+                Some(c) => return Err(format!("Invalid JSON, unknown escape `\\{c}`")),
+                None => return Err("Invalid JSON, string is not closed".to_owned()),
+            },
+            Some(c) => string.push(c),
+            None => return Err("Invalid JSON, string is not closed".to_owned()),
+        }
+    }
+}
+
+/// Parses the 4 hex digits of a `\u` escape.
+fn parse_unicode_escape(chars: &mut Peekable<Chars<'_>>) -> Result<u32, String> {
+    let hex = chars.by_ref().take(4).collect::<String>();
+    if hex.len() != 4 {
+        return Err("Invalid JSON, invalid unicode escape".to_owned());
+    }
+    u32::from_str_radix(&hex, 16)
+        .map_err(|_| format!("Invalid JSON, invalid unicode escape `{hex}`"))
+}
+
+/// Parses a JSON number.
+fn parse_number(chars: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+    let mut number = String::new();
+    while let Some(c) = chars.peek() {
+        match c {
+            '0'..='9' | '-' | '+' | '.' | 'e' | 'E' => {
+                number.push(*c);
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    if number.parse::<f64>().is_err() || number.starts_with('+') {
+        return Err(format!("Invalid JSON number `{number}`"));
+    }
+    Ok(number)
+}
+
+/// Parses a JSON keyword. e.g. `true`, `false` and `null`
+fn parse_keyword(chars: &mut Peekable<Chars<'_>>) -> Result<JsonValue, String> {
+    let mut keyword = String::new();
+    while let Some(c) = chars.peek() {
+        if !c.is_ascii_alphabetic() {
+            break;
+        }
+        keyword.push(*c);
+        chars.next();
+    }
+    match keyword.as_str() {
+        "true" => Ok(JsonValue::Bool(true)),
+        "false" => Ok(JsonValue::Bool(false)),
+        "null" => Ok(JsonValue::Null),
+        _ => Err(format!("Invalid JSON, unknown keyword `{keyword}`")),
+    }
+}
+
+/// Parses a JSON value, nested in the given depth of arrays and objects.
+fn parse_value(chars: &mut Peekable<Chars<'_>>, depth: usize) -> Result<JsonValue, String> {
+    skip_whitespaces(chars);
+    if matches!(chars.peek(), Some('[' | '{')) && depth >= MAX_NESTING_DEPTH {
+        return Err(format!(
+            "Invalid JSON, maximum nesting depth of {MAX_NESTING_DEPTH} exceeded"
+        ));
+    }
+    let value = match chars.peek() {
+        Some('"') => JsonValue::String(parse_string(chars)?),
+        Some('0'..='9' | '-') => JsonValue::Number(parse_number(chars)?),
+        Some('[') => {
+            chars.next();
+            let mut array = Vec::new();
+            skip_whitespaces(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+            } else {
+                loop {
+                    array.push(parse_value(chars, depth + 1)?);
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => break,
+                        _ => return Err("Invalid JSON, expected `,` or `]` in array".to_owned()),
+                    }
+                }
+            }
+            JsonValue::Array(array)
+        }
+        Some('{') => {
+            chars.next();
+            let mut object = Vec::new();
+            skip_whitespaces(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+            } else {
+                loop {
+                    skip_whitespaces(chars);
+                    let key = parse_string(chars)?;
+                    skip_whitespaces(chars);
+                    expect_char(chars, ':')?;
+                    object.push((key, parse_value(chars, depth + 1)?));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => break,
+                        _ => return Err("Invalid JSON, expected `,` or `}` in object".to_owned()),
+                    }
+                }
+            }
+            JsonValue::Object(object)
+        }
+        Some(_) => parse_keyword(chars)?,
+        None => return Err("Invalid JSON, expected a value, found EOF".to_owned()),
+    };
+    skip_whitespaces(chars);
+    Ok(value)
+}
+
+/// Writes the given string as a quoted JSON string.
+fn write_string(f: &mut std::fmt::Formatter, string: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl JsonValue {
+    /// Parses the given JSON text.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars, 0)?;
+        if let Some(c) = chars.next() {
+            return Err(format!("Invalid JSON, unexpected `{c}` after the value"));
+        }
+        Ok(value)
+    }
+
+    /// Returns the value at the given path.
+    pub fn get_path(&self, path: &[PathSegment]) -> Option<&JsonValue> {
+        path.iter()
+            .try_fold(self, |value, segment| match (value, segment) {
+                (JsonValue::Object(object), PathSegment::Key(key)) => {
+                    object.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+                }
+                (JsonValue::Array(array), PathSegment::Index(idx)) => array.get(*idx),
+                _ => None,
+            })
+    }
+
+    /// Returns the value as a cell content, strings are not quoted.
+    pub fn to_cell_string(&self) -> String {
+        match self {
+            JsonValue::String(string) => string.clone(),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// Parses a simple JSON path. e.g. `a.b[0]`, `[1].name`
+pub fn parse_path(path: &str) -> Result<Vec<PathSegment>, String> {
+    let mut segments = Vec::new();
+    let mut chars = path.chars().peekable();
+    let mut key = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' | '[' => {
+                if !key.is_empty() {
+                    segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                } else if c == '.' && !matches!(segments.last(), Some(PathSegment::Index(_))) {
+                    return Err(format!("Invalid JSON path `{path}`, empty key"));
+                }
+                if c == '[' {
+                    let index = chars.by_ref().take_while(|c| *c != ']').collect::<String>();
+                    segments.push(PathSegment::Index(index.trim().parse().map_err(|_| {
+                        format!("Invalid JSON path `{path}`, invalid index `{index}`")
+                    })?));
+                }
+            }
+            c => key.push(c),
+        }
+    }
+    if !key.is_empty() {
+        segments.push(PathSegment::Key(key));
+    } else if path.ends_with('.') {
+        return Err(format!("Invalid JSON path `{path}`, empty key"));
+    }
+    Ok(segments)
+}
+
+/// Converts the given JSON array of objects to a CSV sheet delimited by the given delimiter. The header is
+/// the union of the objects keys, in the order they are first seen, and the missing keys and the nulls are
+/// empty cells. The fields are quoted as needed (RFC 4180).
+pub fn array_to_csv(text: &str, delimiter: char) -> Result<String, String> {
+    let JsonValue::Array(array) = JsonValue::parse(text)? else {
+        return Err("Invalid JSON sheet, expected an array of objects".to_owned());
+    };
+    let objects = array
+        .iter()
+        .map(|value| match value {
+            JsonValue::Object(object) => Ok(object),
+            value => Err(format!(
+                "Invalid JSON sheet, expected an array of objects, found `{value}`"
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut header: Vec<&str> = Vec::new();
+    for (key, _) in objects.iter().flat_map(|object| object.iter()) {
+        if !header.contains(&key.as_str()) {
+            header.push(key);
+        }
+    }
+
+    let csv_error = |err: csv::Error| format!("Invalid JSON sheet, CSV error `{err}`");
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter as u8)
+        .from_writer(Vec::new());
+    writer.write_record(&header).map_err(csv_error)?;
+    for object in objects {
+        writer
+            .write_record(header.iter().map(|key| {
+                object
+                    .iter()
+                    .find(|(k, v)| k == key && v != &JsonValue::Null)
+                    .map(|(_, value)| value.to_cell_string())
+                    .unwrap_or_default()
+            }))
+            .map_err(csv_error)?;
+    }
+    let sheet = writer
+        .into_inner()
+        .map_err(|err| format!("Invalid JSON sheet, CSV error `{}`", err.error()))?;
+    String::from_utf8(sheet).map_err(|_| "Invalid JSON sheet, the CSV is not UTF-8".to_owned())
+}
+
+impl std::fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(boolean) => write!(f, "{boolean}"),
+            JsonValue::Number(number) => write!(f, "{number}"),
+            JsonValue::String(string) => write_string(f, string),
+            JsonValue::Array(array) => {
+                write!(f, "[")?;
+                for (i, element) in array.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(object) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in object.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{config::EngineConfig, engine::Engine};
+
+    /// Reads the records of the given CSV sheet.
+    fn read_records(sheet: &str, delimiter: u8) -> Vec<Vec<String>> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(delimiter)
+            .from_reader(sheet.as_bytes())
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_owned).collect())
+            .collect()
+    }
+
+    #[test]
+    fn array_to_csv_unions_the_keys_in_their_order() {
+        let sheet =
+            array_to_csv(r#"[{"a":1,"b":"x"},{"c":true,"a":null},{"b":[1,2]}]"#, ',').unwrap();
+        assert_eq!(
+            read_records(&sheet, b','),
+            vec![
+                vec!["a", "b", "c"],
+                vec!["1", "x", ""],
+                vec!["", "", "true"],
+                vec!["", "[1,2]", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn array_to_csv_quotes_the_special_values() {
+        let json = r#"[{"name":"Doe, John","note":"line1\nline2","quote":"\"q\" x"}]"#;
+        let sheet = array_to_csv(json, ',').unwrap();
+        assert_eq!(
+            read_records(&sheet, b','),
+            vec![
+                vec!["name", "note", "quote"],
+                vec!["Doe, John", "line1\nline2", "\"q\" x"],
+            ]
+        );
+        let sheet = array_to_csv(r#"[{"a;b":"c;d"}]"#, ';').unwrap();
+        assert_eq!(read_records(&sheet, b';'), vec![vec!["a;b"], vec!["c;d"]]);
+    }
+
+    #[test]
+    fn array_to_csv_rejects_non_arrays_of_objects() {
+        assert!(array_to_csv(r#"{"a":1}"#, ',').is_err());
+        assert!(array_to_csv("[1,2]", ',').is_err());
+        assert!(array_to_csv("[", ',').is_err());
+    }
+
+    #[test]
+    fn evaluates_the_formulas_of_a_json_sheet() {
+        let sheet = array_to_csv(
+            r#"[{"price":2,"qty":3,"total":"=mul(A1;B1)"},{"price":1.5,"qty":2,"total":"=mul(A2;B2)"}]"#,
+            ',',
+        )
+        .unwrap();
+        let output = Engine::new(PathBuf::from("test.json"), &sheet, EngineConfig::default())
+            .and_then(|mut engine| engine.run_to_string())
+            .map_err(|err| err.message)
+            .unwrap();
+        assert_eq!(output, "price,qty,total\n2,3,6\n1.5,2,3.0\n");
+    }
+
+    #[test]
+    fn parses_and_serializes_the_values() {
+        let json = r#"{"a":[1,-2.5e3,true,null],"b":{"c":"x\"y\u00e9"}}"#;
+        let value = JsonValue::parse(json).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,-2.5e3,true,null],"b":{"c":"x\"yé"}}"#
+        );
+        let path = parse_path("b.c").unwrap();
+        assert_eq!(
+            value.get_path(&path).map(JsonValue::to_cell_string),
+            Some("x\"yé".to_owned())
+        );
+        let path = parse_path("a[1]").unwrap();
+        assert_eq!(
+            value.get_path(&path).map(JsonValue::to_cell_string),
+            Some("-2.5e3".to_owned())
+        );
+        assert!(value.get_path(&parse_path("a[9]").unwrap()).is_none());
+    }
+
+    #[test]
+    fn rejects_the_invalid_json() {
+        for json in ["", "[1,", "{\"a\"}", "tru", "+1", "\"\\x\"", "1 2"] {
+            assert!(JsonValue::parse(json).is_err(), "{json}");
+        }
+        assert!(parse_path("a..b").is_err());
+        assert!(parse_path("a[x]").is_err());
+    }
+
+    #[test]
+    fn limits_the_nesting_depth() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_NESTING_DEPTH)).is_ok());
+        let err = JsonValue::parse(&nested(MAX_NESTING_DEPTH + 1)).unwrap_err();
+        assert!(err.contains("maximum nesting depth"), "{err}");
+        // Deep enough to overflow the stack without the limit
+        assert!(JsonValue::parse(&"[".repeat(200_000)).is_err());
+        assert!(JsonValue::parse(&"{\"a\":".repeat(200_000)).is_err());
+    }
+}
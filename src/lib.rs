@@ -0,0 +1,19 @@
+//! A minimal excel-like formulas engine written in Rust without UI.
+
+pub mod ast;
+pub mod base64;
+pub mod builtins;
+pub mod cache;
+pub mod config;
+pub mod constants;
+pub mod date;
+pub mod digest;
+pub mod encoding;
+pub mod engine;
+pub mod errors;
+pub mod json;
+pub mod parser;
+pub mod rng;
+pub mod tokenizer;
+pub mod utils;
+pub mod value;
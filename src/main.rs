@@ -1,53 +1,270 @@
-use std::{fs, process::exit};
+use std::{fs, io, path::Path, process::exit};
 
-mod ast;
-mod builtins;
-mod engine;
-mod errors;
-mod parser;
-mod tokenizer;
-mod utils;
+use minicel_rs::{builtins, digest, engine, json, utils};
+
+mod cli;
+mod self_test;
 
 const fn help_message() -> &'static str {
-    "Usage: minicel-rs <input.csv> <out.csv>"
+    "Usage: minicel-rs <input.csv> <out.csv> [options]
+       minicel-rs --detect-types <input.csv> [options]
+       minicel-rs --dump-deps-dot <input.csv> [options]
+       minicel-rs --data <csv> [out.csv] [options]
+
+Options:
+  --encoding-out <encoding>    The output encoding, `utf-8` (default) or `latin1`
+  --encoding-errors <mode>     What to do with unencodable characters, `error` (default) or `replace`
+  --normalize-newlines <style> Normalize the newlines inside the output fields, `lf` or `crlf`
+  --only-formulas              Write only the formula cells and their results as `cell,result`
+  --seed <number>              The seed of the random builtin functions, for reproducible results
+  --deterministic              Reject the nondeterministic builtin functions unless there is a `--seed`
+  --max-line-length <bytes>    The maximum length of an input line, 1048576 (1 MiB) by default
+  --max-string-length <chars>  The maximum length of a builtin function result, 1048576 by default
+  --max-output-size <bytes>    The maximum size of the output, 4294967296 (4 GiB) by default
+  --flush-interval <rows>      Flush the output every N rows, 0 flushes only at the end, 100 by default
+  --allow-self-reference       Evaluate the circular references iteratively instead of failing
+  --max-iterations <count>     The maximum iterations of a circular reference, 100 by default
+  --iteration-tolerance <number> The change between two iterations to stop iterating, 0.001 by default
+  --constants-precision <digits> The fraction digits of the `pi` and `e` constants, at most 1000, 100 by default
+  --max-arguments <count>      The maximum arguments of a call or elements of an array, 65536 by default
+  --plain-number-limit <digits> The exponent limit of the plain number results, 64 by default
+  --dedupe-columns             Drop the columns with a duplicate header name or contents
+  --no-boolean-cells           Read the referenced `true`/`false` cells as strings instead of booleans
+  --allow-unknown-functions    Keep the formulas that call an unknown function unchanged
+  --coerce-bool                Accept 0/1 and \"yes\"/\"no\" as booleans in the builtin functions
+  --coerce-text-numbers        Accept numeric strings (e.g. \"42\") in the numeric builtin functions
+  --decimal-comma              Read the referenced `1,5` like cells as numbers, needs a non-comma delimiter
+  --skip-errors                Skip the error cells in `sum`, `avg`, `min` and `max` instead of failing
+  --keep-going-rows            Write the records that fail to evaluate unchanged instead of stopping
+  --tee                        Write the output to stdout too
+  --arg-separator <char>       An argument separator accepted in the formulas, in addition to `;`
+  --header-prefix <char>       The prefix of the header-name references (e.g. `$age`), `$` by default
+  --delimiter <char>           The CSV field delimiter (e.g. `;` or `tab`), `,` by default
+  --columns <letters>          The columns to write and their order (e.g. `C,A`), after the evaluation
+  --only-rows <rows>           The data rows to evaluate and write (e.g. `2-5,8`), all the rows by default
+  --other-rows <mode>          What to do with the rows not in `--only-rows`, `drop` (default) or `copy`
+  --sample <count>             Evaluate and write only a random sample of the data rows, seedable with `--seed`
+  --rounding-mode <mode>       The `round` rounding mode, `half-up` (default), `half-even`, `floor`, `ceil` or `toward-zero`
+  --format-profile <profile>   Format the formulas results for `excel`, or as evaluated (`default`)
+  --strip-formulas             Write only the values, escaping the cells that would be read as formulas
+  --keep-formulas <path>       Write the formula cells with their formulas and results to the file too
+  --null-value <value>         The value written in place of the empty output cells, empty by default
+  --output-bom                 Write a UTF-8 byte order mark at the start of the output (e.g. for Excel)
+  --trace-file <path>          Write the evaluation of each formula cell to the file as JSON lines
+  --cache-file <path>          The file of the formulas evaluation cache, reused across the runs
+  --trailing-newline <yes|no>  End the output with a newline, `yes` (default) or `no`
+  --columns-from-row <row>     The row of the header, the rows above it are written unchanged, 1 by default
+  --drop-preamble              Drop the rows above the header instead of writing them unchanged
+  --strict-header              Reject the header with empty or duplicate names
+  --progress                   Draw a progress bar of the processed rows on stderr, if it's a terminal
+  --input-format <format>      The input file format, `csv` (default) or `json` (an array of objects)
+  --data <csv>                 The inline CSV content instead of an input file, `\\n` is a newline
+  --input-glob                 The input path is a glob of the files to concatenate (e.g. \"data/*.csv\")
+  --detect-types               Print the inferred type of each column of the input file and exit
+  --dump-deps-dot              Print the cells dependency graph of the input file in GraphViz DOT and exit
+  --checksum                   Print the SHA-256 checksum of the output file to stderr
+  --explain-errors             Print a remediation hint after the evaluation error
+  --report-unused-columns      Report the columns that are not referenced by any formula to stderr
+  --assert <cell==value>       Assert the evaluated value of a cell (e.g. `C3==42`), can be repeated
+  --list-builtins              Print the builtin functions with their signatures and exit"
+}
+
+/// Reads the given input file as a CSV sheet.
+fn read_input(
+    path: &Path,
+    input_format: cli::InputFormat,
+    delimiter: char,
+) -> Result<String, String> {
+    match input_format {
+        cli::InputFormat::Csv => utils::check_csv_file_path(path, true)?,
+        cli::InputFormat::Json => utils::check_file_path(path, true, "json")?,
+    }
+    let input_content = fs::read_to_string(path)
+        .map_err(|_| format!("IO error: Cannot read the input file {}", path.display()))?;
+    match input_format {
+        cli::InputFormat::Csv => Ok(input_content),
+        cli::InputFormat::Json => json::array_to_csv(&input_content, delimiter),
+    }
+}
+
+/// Reads the input sheet, or concatenates the input sheets matching the glob pattern.
+fn read_sheets(
+    input_path: &Path,
+    input_glob: bool,
+    input_format: cli::InputFormat,
+    delimiter: char,
+) -> Result<String, String> {
+    let input_paths = if input_glob {
+        utils::glob_paths(input_path)?
+    } else {
+        vec![input_path.to_path_buf()]
+    };
+    let sheets = input_paths
+        .into_iter()
+        .map(|path| read_input(&path, input_format, delimiter).map(|sheet| (path, sheet)))
+        .collect::<Result<Vec<_>, _>>()?;
+    utils::concat_sheets(sheets)
 }
 
 fn main() {
-    let _args: Vec<String> = std::env::args().collect();
     pretty_env_logger::init();
 
-    let args = vec![".".to_owned(), "test.csv".to_owned(), "out.csv".to_owned()];
+    let cli = match cli::Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(error) => {
+            eprintln!("{error}\n\n{}", help_message());
+            exit(1);
+        }
+    };
+    let (input_path, output_path) = match &cli.action {
+        cli::Action::Run { input, output } => (input.as_path(), Some(output.as_path())),
+        cli::Action::RunData { output, .. } => (Path::new("<data>"), output.as_deref()),
+        cli::Action::DetectTypes { input } | cli::Action::DumpDepsDot { input } => {
+            (input.as_path(), None)
+        }
+        cli::Action::SelfTest => {
+            if !self_test::run() {
+                exit(1);
+            }
+            return;
+        }
+        cli::Action::ListBuiltins => {
+            println!("{}", builtins::signatures());
+            return;
+        }
+    };
 
-    if args.len() != 3 {
-        println!("{}", help_message());
-        exit(1);
+    let csv_content = match &cli.action {
+        cli::Action::RunData { data, .. } => match cli.input_format {
+            cli::InputFormat::Csv => Ok(data.clone()),
+            cli::InputFormat::Json => json::array_to_csv(data, cli.config.delimiter),
+        },
+        _ => read_sheets(
+            input_path,
+            cli.input_glob,
+            cli.input_format,
+            cli.config.delimiter,
+        ),
+    };
+    let csv_content = match csv_content {
+        Ok(csv_content) => csv_content,
+        Err(error) => {
+            eprintln!("{error}");
+            exit(1);
+        }
+    };
+    if let cli::Action::DetectTypes { .. } = cli.action {
+        let types = engine::Engine::new(input_path.to_path_buf(), &csv_content, cli.config)
+            .and_then(|mut engine| engine.detect_types());
+        match types {
+            Ok(types) => {
+                for (col, (name, column_type)) in types.into_iter().enumerate() {
+                    println!(
+                        "{} {name}: {}",
+                        utils::col_alpha_from_number(col),
+                        column_type.name()
+                    );
+                }
+                return;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1)
+            }
+        }
     }
-    let input_path = std::path::Path::new(&args[1]);
-    let output_path = std::path::Path::new(&args[2]);
-
-    if let Err(error) = utils::check_csv_file_path(input_path, true) {
-        println!("{error}");
-        exit(1);
+    if let cli::Action::DumpDepsDot { .. } = cli.action {
+        let dot = engine::Engine::new(input_path.to_path_buf(), &csv_content, cli.config)
+            .and_then(|engine| engine.dependencies_dot());
+        match dot {
+            Ok(dot) => {
+                println!("{dot}");
+                return;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1)
+            }
+        }
     }
-    if let Err(error) = utils::check_csv_file_path(output_path, false) {
-        println!("{error}");
+    if let Err(error) = output_path.map_or(Ok(()), |output_path| {
+        utils::check_csv_file_path(output_path, false)
+    }) {
+        eprintln!("{error}");
         exit(1);
     }
 
-    let Ok(csv_content) = fs::read_to_string(input_path) else {
-        println!("IO error: Cannot read the input file");
-        exit(1);
-    };
-
-    match engine::Engine::new(input_path.to_path_buf(), &csv_content) {
+    match engine::Engine::new(input_path.to_path_buf(), &csv_content, cli.config) {
         Ok(mut engine) => {
-            if let Err(err) = engine.run(output_path) {
-                println!("{err}");
+            let result = match output_path {
+                Some(output_path) => engine.run(output_path),
+                None => engine.run_to_writer(io::stdout().lock()),
+            };
+            if let Err(err) = result {
+                eprintln!("{err}");
+                if let Some(snippet) = err.snippet() {
+                    eprintln!("{snippet}");
+                }
+                if let Some(hint) = cli
+                    .explain_errors
+                    .then(|| engine.explain_error(&err))
+                    .flatten()
+                {
+                    eprintln!("hint: {hint}");
+                }
+                exit(1);
+            }
+            if let Some(output_path) = output_path.filter(|_| cli.checksum) {
+                let Ok(output) = fs::read(output_path) else {
+                    eprintln!("IO error: Cannot read the output file");
+                    exit(1);
+                };
+                eprintln!(
+                    "{}  {}",
+                    digest::to_hex(&digest::sha256(&output)),
+                    output_path.display()
+                );
+            }
+            if cli.report_unused_columns {
+                match engine.unused_columns() {
+                    Ok(columns) => {
+                        for (col, name) in columns {
+                            eprintln!("Unused column {} {name}", utils::col_alpha_from_number(col));
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("{err}");
+                        exit(1)
+                    }
+                }
+            }
+            let mut failed_assertions = 0;
+            for assertion in &cli.assertions {
+                match engine.evaluate_cell(assertion.col, assertion.row) {
+                    Ok(value) if value == assertion.expected => {}
+                    Ok(value) => {
+                        eprintln!(
+                            "Assertion failed: {}\n  expected: {}\n  found:    {value}",
+                            assertion.cell, assertion.expected
+                        );
+                        failed_assertions += 1;
+                    }
+                    Err(err) => {
+                        eprintln!("Assertion failed: {}\n  {err}", assertion.cell);
+                        failed_assertions += 1;
+                    }
+                }
+            }
+            if failed_assertions != 0 {
+                eprintln!(
+                    "{failed_assertions} of {} assertions failed",
+                    cli.assertions.len()
+                );
                 exit(1);
             }
         }
         Err(err) => {
-            println!("{err}");
+            eprintln!("{err}");
             exit(1)
         }
     }
@@ -1,17 +1,38 @@
 use multipeek::MultiPeek;
 
-use crate::ast::{Ast, Expression, FunctionCallExpression};
+use crate::ast::{Ast, Expression, FunctionCallExpression, Span};
 use crate::tokenizer::Token;
+use crate::utils;
 
 use crate::errors::{
     Error as MinicelError, ErrorKind as MinicelErrorKind, Result as MinicelResult,
 };
 
+/// The maximum nesting depth of the function calls and arrays.
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// The default maximum number of the arguments of a function call, or the elements of an array.
+pub const DEFAULT_MAX_ARGUMENTS: usize = 65536;
+
 /// The parser
 #[derive(Debug)]
 pub struct Parser<'a> {
     tokens: MultiPeek<std::slice::Iter<'a, Token>>,
     line_number: usize,
+    /// The current nesting depth of the function calls and arrays
+    depth: usize,
+    /// The maximum number of the arguments of a function call, or the elements of an array
+    max_arguments: usize,
+    /// The header names, to resolve the header-name references
+    header: &'a [String],
+    /// The row of the parsed field, the row of the header-name references
+    row: u64,
+    /// The rows of the sheet, the last row of the ranges with an open end
+    rows: u64,
+    /// The spans of the tokens
+    spans: &'a [Span],
+    /// The index of the next token
+    position: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -20,7 +41,90 @@ impl<'a> Parser<'a> {
         Self {
             tokens,
             line_number,
+            depth: 0,
+            max_arguments: DEFAULT_MAX_ARGUMENTS,
+            header: &[],
+            row: 0,
+            rows: 0,
+            spans: &[],
+            position: 0,
+        }
+    }
+
+    /// Sets the spans of the tokens, the spans of the parsed expressions are the ranges of their tokens.
+    pub fn with_spans(mut self, spans: &'a [Span]) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    /// Consumes the next token.
+    fn next_token(&mut self) {
+        self.tokens.next();
+        self.position += 1;
+    }
+
+    /// Returns the span from the start of the token at the given position to the end of the last
+    /// consumed token, an empty span without the tokens spans.
+    fn span_since(&self, position: usize) -> Span {
+        match (
+            self.spans.get(position),
+            self.position
+                .checked_sub(1)
+                .and_then(|last| self.spans.get(last)),
+        ) {
+            (Some(start), Some(end)) => start.start..end.end,
+            _ => 0..0,
+        }
+    }
+
+    /// Sets the header names and the row of the parsed field, the header-name references are the
+    /// cells of their column in this row.
+    pub fn with_header(mut self, header: &'a [String], row: u64) -> Self {
+        self.header = header;
+        self.row = row;
+        self
+    }
+
+    /// Sets the rows of the sheet, the ranges with an open end (e.g. `A2:A`) end at the last row.
+    pub fn with_rows(mut self, rows: u64) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    /// Sets the maximum number of the arguments of a function call, or the elements of an array.
+    pub fn with_max_arguments(mut self, max_arguments: usize) -> Self {
+        self.max_arguments = max_arguments;
+        self
+    }
+
+    /// Returns an error if the given arguments or elements are already at the maximum.
+    fn check_arguments_count(&self, arguments: &[Expression]) -> MinicelResult<()> {
+        if arguments.len() >= self.max_arguments {
+            log::error!("Maximum arguments count exceeded");
+            return Err(MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!(
+                    "Maximum number of arguments or array elements of {} exceeded",
+                    self.max_arguments
+                ),
+                self.line_number,
+            ));
         }
+        Ok(())
+    }
+
+    /// Enters a nested function call or array, returns an error if the maximum nesting depth is exceeded.
+    fn enter_nesting(&mut self) -> MinicelResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            log::error!("Maximum nesting depth exceeded");
+            return Err(MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!("Maximum nesting depth of {MAX_NESTING_DEPTH} exceeded"),
+                self.line_number,
+            ));
+        }
+        Ok(())
     }
 
     /// Parses the tokens into an AST.
@@ -36,6 +140,19 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Returns the column number (Starting from 0) of the given column letters of the given field
+    /// identifier, an error if it overflows.
+    fn column_number(&self, col: &str, identifier: &str) -> MinicelResult<usize> {
+        utils::col_number_from_alpha(col).ok_or_else(|| {
+            log::error!("The column `{col}` overflows");
+            MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!("Invalid field identifier `{identifier}`, the column `{col}` is too large"),
+                self.line_number,
+            )
+        })
+    }
+
     /// Parses the identifier.
     fn parse_identifier(&mut self) -> MinicelResult<&str> {
         log::info!("Parsing identifier");
@@ -43,7 +160,7 @@ impl<'a> Parser<'a> {
         match self.tokens.peek() {
             Some(Token::Identifier(identifier)) => {
                 log::debug!("Found identifier: {identifier}");
-                self.tokens.next();
+                self.next_token();
                 Ok(identifier)
             }
             Some(token) => {
@@ -68,7 +185,7 @@ impl<'a> Parser<'a> {
     fn parse_field(&mut self) -> MinicelResult<Expression> {
         log::info!("Parsing field");
 
-        let identifier = self.parse_identifier()?;
+        let identifier = self.parse_identifier()?.to_owned();
         let col = identifier
             .chars()
             .take_while(|c| c.is_ascii_alphabetic())
@@ -79,6 +196,18 @@ impl<'a> Parser<'a> {
             .skip_while(|c| c.is_ascii_alphabetic())
             .collect::<String>();
         log::debug!("Found row in the field: {row}");
+        let row_len = row.chars().take_while(|c| c.is_ascii_digit()).count();
+        if !col.is_empty() && row_len > 0 && row_len < row.len() {
+            return Err(MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!(
+                    "Invalid field identifier `{identifier}`, unexpected `{}` after the row number `{}`, a field is the column letters then the row number (e.g. `A1`)",
+                    &row[row_len..],
+                    &row[..row_len]
+                ),
+                self.line_number,
+            ));
+        }
         let row = match row.parse() {
             Ok(row) => {
                 if row == 0 {
@@ -101,6 +230,8 @@ impl<'a> Parser<'a> {
             }
         };
 
+        self.column_number(&col, &identifier)?;
+
         Ok(Expression::Field {
             col,
             row,
@@ -108,6 +239,142 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Parses the header-name reference.
+    /// A header-name reference is the cell of the column with this header name in the row of the field.
+    /// e.g. `$age`, `$"first name"`
+    fn parse_header_field(&mut self, name: &str) -> MinicelResult<Expression> {
+        log::info!("Parsing header-name reference");
+
+        self.next_token();
+        let Some(col) = self.header.iter().position(|header| header.trim() == name) else {
+            log::error!("Unknown header name: {name}");
+            return Err(MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!("Unknown header name `{name}`, there is no column with this header"),
+                self.line_number,
+            ));
+        };
+        log::debug!("Found the header name `{name}` in the column {col}");
+        Ok(Expression::Field {
+            col: utils::col_alpha_from_number(col),
+            row: self.row,
+            value: String::new(),
+        })
+    }
+
+    /// Parses the field of a range end, the column letters alone (e.g. `a`) are an open end, a field
+    /// with the row 0.
+    fn parse_range_end(&mut self) -> MinicelResult<Expression> {
+        match self.tokens.peek() {
+            Some(Token::Identifier(identifier))
+                if identifier.chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                log::debug!("Found the open range end: {identifier}");
+                self.next_token();
+                Ok(Expression::Field {
+                    col: identifier.clone(),
+                    row: 0,
+                    value: String::new(),
+                })
+            }
+            _ => self.parse_field(),
+        }
+    }
+
+    /// Parses the range if the given start field is followed by a colon, otherwise returns the field.
+    /// A range is two fields separated by a colon, the corners are normalized so its start is the top
+    /// left corner. e.g. `a1:b3`, `b3:a1` is `a1:b3`
+    ///
+    /// One of the ends can be the column letters alone, an open end. The open start is the first row
+    /// and the open end is the last row of the sheet. e.g. `a2:a` is `a2:a{rows}` and `a:b5` is `a1:b5`
+    fn parse_range(&mut self, start: Expression) -> MinicelResult<Expression> {
+        if self.tokens.peek() != Some(&&Token::Colon) {
+            return Ok(start);
+        }
+        log::info!("Found colon, parsing range");
+        self.next_token();
+        let end = match self.tokens.peek() {
+            Some(Token::HeaderName(name)) => self.parse_header_field(name)?,
+            Some(Token::Identifier(_)) => self.parse_range_end()?,
+            Some(token) => {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Parse,
+                    format!("Expected the end field of the range, found {token:?}"),
+                    self.line_number,
+                ))
+            }
+            None => {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Parse,
+                    "Expected the end field of the range, found EOF".to_string(),
+                    self.line_number,
+                ))
+            }
+        };
+        let (
+            Expression::Field {
+                col: start_col,
+                row: start_row,
+                ..
+            },
+            Expression::Field {
+                col: end_col,
+                row: end_row,
+                ..
+            },
+        ) = (start, end)
+        else {
+            unreachable!("parse_field and parse_header_field always return a field")
+        };
+        let (start_row, end_row) = match (start_row, end_row) {
+            (0, 0) => {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Parse,
+                    format!(
+                        "Invalid range `{start_col}:{end_col}`, one of its ends must have a row number (e.g. `{start_col}1:{end_col}`)"
+                    ),
+                    self.line_number,
+                ))
+            }
+            (0, end_row) => (1, end_row),
+            (start_row, 0) if start_row > self.rows => {
+                return Err(MinicelError::new(
+                    MinicelErrorKind::Parse,
+                    format!(
+                        "Invalid range `{start_col}{start_row}:{end_col}`, it starts after the last row {}",
+                        self.rows
+                    ),
+                    self.line_number,
+                ))
+            }
+            (start_row, 0) => (start_row, self.rows),
+            rows => rows,
+        };
+        let (start_col, end_col) = (
+            self.column_number(&start_col, &format!("{start_col}{start_row}"))?,
+            self.column_number(&end_col, &format!("{end_col}{end_row}"))?,
+        );
+        let fields_count = (start_col.abs_diff(end_col) as u64 + 1)
+            .saturating_mul(start_row.abs_diff(end_row) + 1);
+        if fields_count > self.max_arguments as u64 {
+            log::error!("Maximum range fields count exceeded");
+            return Err(MinicelError::new(
+                MinicelErrorKind::Parse,
+                format!(
+                    "The range has {fields_count} fields, the maximum number of arguments or array elements is {}",
+                    self.max_arguments
+                ),
+                self.line_number,
+            ));
+        }
+        Ok(Expression::Range {
+            start_col: utils::col_alpha_from_number(start_col.min(end_col)),
+            start_row: start_row.min(end_row),
+            end_col: utils::col_alpha_from_number(start_col.max(end_col)),
+            end_row: start_row.max(end_row),
+        })
+    }
+
     /// Parses the array.
     fn parse_array(&mut self) -> MinicelResult<Expression> {
         log::info!("Parsing array");
@@ -116,19 +383,22 @@ impl<'a> Parser<'a> {
         match self.tokens.peek() {
             Some(Token::LeftBracket) => {
                 log::info!("Found left bracket");
-                self.tokens.next();
+                self.next_token();
+                self.enter_nesting()?;
                 while let Some(token) = self.tokens.peek() {
                     match token {
                         Token::RightBracket => {
                             log::info!("Found right bracket");
-                            self.tokens.next();
+                            self.next_token();
+                            self.depth -= 1;
                             return Ok(Expression::Array(array));
                         }
                         Token::Semicolon => {
-                            self.tokens.next();
+                            self.next_token();
                         }
                         _ => {
                             log::info!("Parsing expression in array");
+                            self.check_arguments_count(&array)?;
                             array.push(self.parse_expression()?);
                         }
                     }
@@ -167,27 +437,37 @@ impl<'a> Parser<'a> {
                             self.parse_function_call()
                         } else if ident == "true" || ident == "false" {
                             log::info!("Found identifier that is a boolean: {ident}");
-                            self.tokens.next();
+                            self.next_token();
                             Ok(Expression::Boolean(ident == "true"))
                         } else {
                             log::info!("Found identifier that is not a function call and not a boolean, parsing field");
-                            self.parse_field()
+                            let field = if self.tokens.peek_nth(1) == Some(&&Token::Colon) {
+                                self.parse_range_end()?
+                            } else {
+                                self.parse_field()?
+                            };
+                            self.parse_range(field)
                         }
                     }
                     Token::Number(n) => {
                         log::debug!("Found number: {n}");
-                        self.tokens.next();
+                        self.next_token();
                         Ok(Expression::Number(n.clone()))
                     }
                     Token::String(s) => {
                         log::debug!("Found string: {s}");
-                        self.tokens.next();
+                        self.next_token();
                         Ok(Expression::String(s.clone()))
                     }
                     Token::LeftBracket => {
                         log::info!("Found left bracket, parsing array");
                         self.parse_array()
                     }
+                    Token::HeaderName(name) => {
+                        log::debug!("Found header name: {name}");
+                        let field = self.parse_header_field(name)?;
+                        self.parse_range(field)
+                    }
                     _ => Err(MinicelError::new(
                         MinicelErrorKind::Parse,
                         format!("Expected expression, found {:?}", token),
@@ -203,29 +483,33 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Parses the arguments.
-    fn parse_arguments(&mut self) -> MinicelResult<Vec<Expression>> {
+    /// Parses the arguments, returns them with their spans.
+    fn parse_arguments(&mut self) -> MinicelResult<(Vec<Expression>, Vec<Span>)> {
         log::info!("Parsing function arguments");
 
         let mut arguments = Vec::new();
+        let mut spans = Vec::new();
         match self.tokens.peek() {
             Some(Token::LeftParenthesis) => {
                 log::info!("Found left parenthesis");
-                self.tokens.next();
+                self.next_token();
                 while let Some(token) = self.tokens.peek() {
                     match token {
                         Token::RightParenthesis => {
                             log::info!("Found right parenthesis, returning arguments");
-                            self.tokens.next();
-                            return Ok(arguments);
+                            self.next_token();
+                            return Ok((arguments, spans));
                         }
                         Token::Semicolon => {
                             log::info!("Found semicolon");
-                            self.tokens.next();
+                            self.next_token();
                         }
                         c => {
                             log::debug!("Found token: {c:?} and parsing it as an expression");
+                            self.check_arguments_count(&arguments)?;
+                            let position = self.position;
                             arguments.push(self.parse_expression()?);
+                            spans.push(self.span_since(position));
                         }
                     }
                 }
@@ -252,12 +536,185 @@ impl<'a> Parser<'a> {
     fn parse_function_call(&mut self) -> MinicelResult<Expression> {
         log::info!("Parsing function call");
 
+        let position = self.position;
         let name = self.parse_identifier()?.to_string();
-        let arguments = self.parse_arguments()?;
+        self.enter_nesting()?;
+        let (arguments, argument_spans) = self.parse_arguments()?;
+        self.depth -= 1;
         Ok(Expression::FunctionCall(FunctionCallExpression {
             name: name.to_string(),
             arguments,
             line_number: self.line_number,
+            span: self.span_since(position),
+            argument_spans,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer;
+
+    /// Parses the given formula, without the `=`, of a sheet with 10 rows.
+    fn parse(formula: &str) -> Result<Ast, String> {
+        let (tokens, spans) =
+            tokenizer::tokenize_with_spans(formula, 1, ';', '$').map_err(|err| err.message)?;
+        Parser::new(multipeek::multipeek(tokens.iter()), 1)
+            .with_spans(&spans)
+            .with_rows(10)
+            .parse()
+            .map_err(|err| err.message)
+    }
+
+    /// Returns the corners of the range argument of the given formula.
+    fn range(formula: &str) -> (String, u64, String, u64) {
+        match parse(formula).unwrap().function.arguments.remove(0) {
+            Expression::Range {
+                start_col,
+                start_row,
+                end_col,
+                end_row,
+            } => (start_col, start_row, end_col, end_row),
+            expression => panic!("Expected a range, found {expression:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_a_range() {
+        assert_eq!(range("sum(A1:B3)"), ("A".into(), 1, "B".into(), 3));
+    }
+
+    #[test]
+    fn normalizes_the_reversed_range_corners() {
+        assert_eq!(range("sum(B3:A1)"), ("A".into(), 1, "B".into(), 3));
+        assert_eq!(range("sum(A3:B1)"), ("A".into(), 1, "B".into(), 3));
+    }
+
+    #[test]
+    fn resolves_the_open_range_end_to_the_last_row() {
+        assert_eq!(range("sum(A2:A)"), ("A".into(), 2, "A".into(), 10));
+        assert_eq!(range("sum(B3:A)"), ("A".into(), 3, "B".into(), 10));
+    }
+
+    #[test]
+    fn resolves_the_open_range_start_to_the_first_row() {
+        assert_eq!(range("sum(A:A5)"), ("A".into(), 1, "A".into(), 5));
+        assert_eq!(range("sum(B:A5)"), ("A".into(), 1, "B".into(), 5));
+    }
+
+    #[test]
+    fn rejects_a_range_without_a_row_number() {
+        assert!(parse("sum(A:B)")
+            .unwrap_err()
+            .contains("one of its ends must have a row number"));
+        assert!(parse("sum(A11:A)")
+            .unwrap_err()
+            .contains("it starts after the last row 10"));
+        assert!(parse("sum(A)").is_err());
+    }
+
+    #[test]
+    fn rejects_a_range_without_an_end() {
+        assert!(parse("sum(A1:)").is_err());
+        assert!(parse("sum(A1:1)").is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_columns_without_panicking() {
+        let column = "A".repeat(21);
+        let err = parse(&format!("sum({column}1:A1)")).unwrap_err();
+        assert!(err.contains("is too large"), "{err}");
+        let err = parse(&format!("sum({column}1;1)")).unwrap_err();
+        assert!(err.contains("is too large"), "{err}");
+    }
+
+    #[test]
+    fn resolves_the_header_name_references_in_the_field_row() {
+        let header = ["name".to_owned(), "first name".to_owned(), "age".to_owned()];
+        let tokens = tokenizer::tokenize("sum($age;$\"first name\")", 1, ';', '$')
+            .map_err(|err| err.message)
+            .unwrap();
+        let ast = Parser::new(multipeek::multipeek(tokens.iter()), 1)
+            .with_header(&header, 4)
+            .parse()
+            .map_err(|err| err.message)
+            .unwrap();
+        let fields = ast.function.referenced_fields();
+        assert_eq!(fields, vec![("B".to_owned(), 4), ("C".to_owned(), 4)]);
+    }
+
+    #[test]
+    fn rejects_unknown_header_names() {
+        let err = parse("sum($age)").unwrap_err();
+        assert!(err.contains("Unknown header name `age`"), "{err}");
+        assert!(tokenizer::tokenize("sum($)", 1, ';', '$').is_err());
+    }
+
+    #[test]
+    fn limits_the_arguments_and_the_array_elements() {
+        let parse_with_max = |formula: &str| {
+            let tokens = tokenizer::tokenize(formula, 1, ';', '$').map_err(|err| err.message)?;
+            Parser::new(multipeek::multipeek(tokens.iter()), 1)
+                .with_rows(10)
+                .with_max_arguments(3)
+                .parse()
+                .map_err(|err| err.message)
+        };
+        assert!(parse_with_max("sum(1;2;3)").is_ok());
+        assert!(parse_with_max("sum([1;2;3];[4;5;6])").is_ok());
+        for formula in ["sum(1;2;3;4)", "sum([1;2;3;4])", "sum(A1:A4)"] {
+            assert!(
+                parse_with_max(formula)
+                    .unwrap_err()
+                    .contains("number of arguments or array elements"),
+                "{formula}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_the_mistyped_field_identifiers_with_distinct_messages() {
+        let a1b2 = parse("sum(A1B2;1)").expect_err("`A1B2` is not a field");
+        let reversed = parse("sum(1A;1)").expect_err("`1A` is not a field");
+        assert_eq!(
+            a1b2,
+            "Invalid field identifier `A1B2`, unexpected `B2` after the row number `1`, a field is \
+             the column letters then the row number (e.g. `A1`)"
+        );
+        assert_eq!(
+            reversed,
+            "Invalid field identifier `1A`, the column letters must precede the row number (e.g. `A1`)"
+        );
+        assert_ne!(a1b2, reversed);
+    }
+
+    #[test]
+    fn maps_the_offsets_to_the_innermost_nodes() {
+        let ast = parse("sum(A1; mul(2; 3))").unwrap();
+        let node = |offset| ast.node_at_offset(offset).map(ToString::to_string);
+        assert_eq!(ast.function.span, 0..18);
+        assert_eq!(ast.function.argument_spans, vec![4..6, 8..17]);
+        for (offset, expected) in [
+            (0, None),
+            (3, None),
+            (4, Some("")),
+            (5, Some("")),
+            (6, None),
+            (8, Some("builtin function: mul(2, 3)")),
+            (11, Some("builtin function: mul(2, 3)")),
+            (12, Some("2")),
+            (13, Some("builtin function: mul(2, 3)")),
+            (15, Some("3")),
+            (16, Some("builtin function: mul(2, 3)")),
+            (17, None),
+            (18, None),
+        ] {
+            assert_eq!(node(offset).as_deref(), expected, "{offset}");
+        }
+        assert!(matches!(
+            ast.node_at_offset(4),
+            Some(Expression::Field { col, row: 1, .. }) if col == "A"
+        ));
+    }
+}
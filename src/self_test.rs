@@ -0,0 +1,157 @@
+//! The self test of the builtin functions, evaluating an example formula of each one.
+
+use std::path::PathBuf;
+
+use minicel_rs::{builtins::BUILTINS, config::EngineConfig, engine::Engine};
+
+/// The examples of the builtin functions, as (builtin, A1 value, formula, expected result).
+/// The formula is the B1 cell, after the A1 value.
+const EXAMPLES: &[(&str, &str, &str, &str)] = &[
+    ("print", "38", "=print(A1)", "38"),
+    ("sum", "", "=sum(1;2;[3])", "6"),
+    ("sub", "", "=sub(1;2)", "-1"),
+    ("mul", "", "=mul(2;3)", "6"),
+    ("div", "", "=div(6;4)", "1.5"),
+    (
+        "sha256",
+        "",
+        "=sha256(\"abc\")",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    ),
+    (
+        "md5",
+        "",
+        "=md5(\"abc\")",
+        "900150983cd24fb0d6963f7d28e17f72",
+    ),
+    ("base64_encode", "", "=base64_encode(\"ab\")", "YWI="),
+    ("base64_decode", "", "=base64_decode(\"YWI=\")", "ab"),
+    (
+        "json_extract",
+        "{\"a\":{\"b\":[7]}}",
+        "=json_extract(A1;\"a.b[0]\")",
+        "7",
+    ),
+    ("iferror", "", "=iferror(div(1;0);\"n/a\")", "n/a"),
+    ("contains", "", "=contains(\"Hello\";\"ell\")", "true"),
+    ("icontains", "", "=icontains(\"Hello\";\"ELL\")", "true"),
+    ("startswith", "", "=startswith(\"Hello\";\"He\")", "true"),
+    ("istartswith", "", "=istartswith(\"Hello\";\"he\")", "true"),
+    ("endswith", "", "=endswith(\"Hello\";\"lo\")", "true"),
+    ("iendswith", "", "=iendswith(\"Hello\";\"LO\")", "true"),
+    ("year", "", "=year(\"2024-03-15\")", "2024"),
+    ("month", "", "=month(\"2024-03-15\")", "3"),
+    ("day", "", "=day(\"2024-03-15\")", "15"),
+    ("weekday", "", "=weekday(\"2024-03-15\")", "5"),
+    ("padleft", "", "=padleft(7;3;\"0\")", "007"),
+    ("padright", "", "=padright(\"ab\";4;\".\")", "ab.."),
+    ("sumproduct", "", "=sumproduct([1;2];[3;4])", "11"),
+    (
+        "distinct_count_if",
+        "",
+        "=distinct_count_if([1;2;2;5];\">\";1)",
+        "2",
+    ),
+    (
+        "textjoin",
+        "",
+        "=textjoin(\"-\";true;[\"a\";\"\";\"b\"])",
+        "a-b",
+    ),
+    (
+        "concat_if",
+        "",
+        "=concat_if([5;12;30];\">\";10;\"|\")",
+        "12|30",
+    ),
+    ("gcd", "", "=gcd(12;-18)", "6"),
+    ("lcm", "", "=lcm(4;6)", "12"),
+    ("round", "", "=round(2.5)", "3"),
+    ("mode", "", "=mode([1;\"b\";2;\"b\";1])", "1"),
+    ("avg", "", "=avg([1;2;3];4)", "2.5"),
+    ("min", "", "=min(3;[-1;[2]])", "-1"),
+    ("max", "", "=max(3;[-1;[7]])", "7"),
+    ("if", "true", "=if(A1;\"yes\";\"no\")", "yes"),
+    ("eq", "1.0", "=eq(A1;1)", "true"),
+    ("gt", "", "=gt(2;1)", "true"),
+    ("lt", "", "=lt(2;1)", "false"),
+    ("gte", "", "=gte(1;1)", "true"),
+    ("lte", "", "=lte(-1;-2)", "false"),
+    ("pi", "", "=round(pi();4)", "3.1416"),
+    ("e", "", "=round(e();4)", "2.7183"),
+    ("and", "true", "=and(A1;gt(2;1))", "true"),
+    ("or", "", "=or(false;\"false\")", "false"),
+    ("not", "", "=not(false)", "true"),
+    ("concat", "", "=concat([\"a\";\"b\"];\"c\";1.50)", "abc1.50"),
+    ("len", "héllo", "=len(A1)", "5"),
+    ("substr", "", "=substr(\"hello\";2;3)", "ell"),
+    ("mid", "", "=mid(\"héllo\";2;10)", "éllo"),
+    ("repeat", "", "=repeat(\"ab\";3)", "ababab"),
+    ("join", "", "=join([\"a\";\"b\"];\"-\")", "a-b"),
+    ("shuffle", "", "=shuffle([1;2;3])", "[3, 1, 2]"),
+    ("rand", "", "=rand()", "0.208416658607535"),
+];
+
+/// Evaluates the example formula in a one record sheet, returning its result.
+fn evaluate_example(value: &str, formula: &str) -> Result<String, String> {
+    let sheet = format!("value,result\n{value},{formula}\n");
+    let config = EngineConfig {
+        seed: Some(0),
+        ..EngineConfig::default()
+    };
+    let output = Engine::new(PathBuf::from("self-test.csv"), &sheet, config)
+        .and_then(|mut engine| engine.run_to_string())
+        .map_err(|err| err.to_string())?;
+    csv::Reader::from_reader(output.as_bytes())
+        .records()
+        .next()
+        .and_then(Result::ok)
+        .and_then(|record| record.get(1).map(str::to_owned))
+        .ok_or_else(|| format!("Unexpected output `{output}`"))
+}
+
+/// Runs the self test, printing the result of each builtin function. Returns whether all of them passed.
+pub fn run() -> bool {
+    let mut passed = true;
+    for (name, _, _) in BUILTINS {
+        let Some((_, value, formula, expected)) =
+            EXAMPLES.iter().find(|(builtin, ..)| builtin == name)
+        else {
+            println!("FAIL {name}: no example");
+            passed = false;
+            continue;
+        };
+        match evaluate_example(value, formula) {
+            Ok(result) if &result == expected => println!("ok   {name}"),
+            Ok(result) => {
+                println!("FAIL {name}: `{formula}` expected `{expected}`, found `{result}`");
+                passed = false;
+            }
+            Err(err) => {
+                println!("FAIL {name}: `{formula}` {err}");
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_builtin_has_an_example() {
+        for (name, _, _) in BUILTINS {
+            assert!(
+                EXAMPLES.iter().any(|(builtin, ..)| builtin == name),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn passes_for_the_default_builtins() {
+        assert!(run());
+    }
+}
@@ -1,14 +1,13 @@
 //! The tokenizer of the minicel-rs, this tokenizer only tokenizes the functions calls and its arguments.
 
-use std::{
-    iter::Peekable,
-    str::{Chars, FromStr},
-};
+use std::{iter::Peekable, str::CharIndices};
 
 use bigdecimal::BigDecimal;
 
-use crate::errors::{
-    Error as MinicelError, ErrorKind as MinicelErrorKind, Result as MinicelResult,
+use crate::{
+    ast::Span,
+    errors::{Error as MinicelError, ErrorKind as MinicelErrorKind, Result as MinicelResult},
+    utils,
 };
 
 /// The tokens that the tokenizer can produce.
@@ -20,7 +19,7 @@ pub enum Token {
     String(String),
     /// Number token, this token is used to represent the number arguments of the function.
     Number(BigDecimal),
-    /// Semicolon token, this token is used to represent the semicolon that separates the arguments of the function.
+    /// Semicolon token, this token is used to represent the semicolon (or the configured separator) that separates the arguments of the function.
     Semicolon,
     /// Left Parenthesis token, this token is used to represent the left parenthesis that opens the function call.
     LeftParenthesis,
@@ -30,13 +29,17 @@ pub enum Token {
     LeftBracket,
     /// Right Bracket token, this token is used to represent the close of array.
     RightBracket,
+    /// Colon token, this token is used to represent the colon that separates the corners of a range.
+    Colon,
+    /// Header Name token, this token is used to represent the header-name references, the name without the prefix.
+    HeaderName(String),
 }
 
 /// Read the string
-fn read_string(field: &mut Peekable<Chars<'_>>, line_number: usize) -> MinicelResult<Token> {
+fn read_string(field: &mut Peekable<CharIndices<'_>>, line_number: usize) -> MinicelResult<Token> {
     let mut string = String::new();
     let mut is_closed = false;
-    for c in field.by_ref() {
+    for (_, c) in field.by_ref() {
         if c == '"' {
             is_closed = true;
             break;
@@ -54,38 +57,17 @@ fn read_string(field: &mut Peekable<Chars<'_>>, line_number: usize) -> MinicelRe
     }
 }
 
-/// Read the number
-fn read_number(field: &mut Peekable<Chars<'_>>, line_number: usize) -> MinicelResult<Token> {
+/// Read the number, the number rules are the [`utils::parse_number`] ones
+fn read_number(
+    field: &mut Peekable<CharIndices<'_>>,
+    line_number: usize,
+    separator: char,
+) -> MinicelResult<Token> {
     let mut number = String::new();
-    let mut is_float = false;
-    let mut is_negative = false;
-    while let Some(c) = field.peek() {
+    while let Some((_, c)) = field.peek() {
         match c {
-            '0'..='9' => {
-                number.push(*c);
-                field.next();
-            }
-            '.' => {
-                if is_float {
-                    return Err(MinicelError::new(
-                        MinicelErrorKind::Tokenizer,
-                        "Invalid float number".to_string(),
-                        line_number,
-                    ));
-                }
-                is_float = true;
-                number.push(*c);
-                field.next();
-            }
-            '-' => {
-                if is_negative || !number.is_empty() {
-                    return Err(MinicelError::new(
-                        MinicelErrorKind::Tokenizer,
-                        "Invalid negative number".to_string(),
-                        line_number,
-                    ));
-                }
-                is_negative = true;
+            c if *c == separator => break,
+            '0'..='9' | 'a'..='z' | 'A'..='Z' | '.' | '-' | '+' | '_' => {
                 number.push(*c);
                 field.next();
             }
@@ -93,15 +75,29 @@ fn read_number(field: &mut Peekable<Chars<'_>>, line_number: usize) -> MinicelRe
         }
     }
 
-    Ok(Token::Number(
-        BigDecimal::from_str(&number).expect("is a number"),
-    ))
+    utils::parse_number(&number)
+        .map(Token::Number)
+        .ok_or_else(|| {
+            // A row number followed by column letters is a reversed field identifier. e.g. `1A`
+            let letters = number.trim_start_matches(|c: char| c.is_ascii_digit());
+            let message = if letters.len() < number.len()
+                && letters.starts_with(|c: char| c.is_ascii_uppercase())
+                && letters.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                format!(
+                    "Invalid field identifier `{number}`, the column letters must precede the row number (e.g. `A1`)"
+                )
+            } else {
+                format!("Invalid number `{number}`")
+            };
+            MinicelError::new(MinicelErrorKind::Tokenizer, message, line_number)
+        })
 }
 
 /// Read the identifier
-fn read_identifier(field: &mut Peekable<Chars<'_>>) -> Token {
+fn read_identifier(field: &mut Peekable<CharIndices<'_>>) -> Token {
     let mut identifier = String::new();
-    while let Some(c) = field.peek() {
+    while let Some((_, c)) = field.peek() {
         match c {
             '_' | 'a'..='z' | 'A'..='Z' | '0'..='9' => {
                 identifier.push(*c);
@@ -113,44 +109,95 @@ fn read_identifier(field: &mut Peekable<Chars<'_>>) -> Token {
     Token::Identifier(identifier)
 }
 
-/// Tokenize the given field.
-pub fn tokenize(field: &str, line_number: usize) -> MinicelResult<Vec<Token>> {
-    let mut field = field.chars().peekable();
+/// Read the header name after the header prefix, an identifier or a string. e.g. `age` or `"first name"`
+fn read_header_name(
+    field: &mut Peekable<CharIndices<'_>>,
+    line_number: usize,
+    header_prefix: char,
+) -> MinicelResult<Token> {
+    let name = if matches!(field.peek(), Some((_, '"'))) {
+        field.next();
+        match read_string(field, line_number)? {
+            Token::String(name) => name,
+            _ => unreachable!("read_string always returns a string token"),
+        }
+    } else {
+        match read_identifier(field) {
+            Token::Identifier(name) => name,
+            _ => unreachable!("read_identifier always returns an identifier token"),
+        }
+    };
+    if name.is_empty() {
+        return Err(MinicelError::new(
+            MinicelErrorKind::Tokenizer,
+            format!("Expected a header name after `{header_prefix}`"),
+            line_number,
+        ));
+    }
+    Ok(Token::HeaderName(name))
+}
+
+/// Tokenize the given field. The arguments are separated by `;` or the given separator, and the
+/// header-name references start with the given header prefix.
+pub fn tokenize(
+    field: &str,
+    line_number: usize,
+    separator: char,
+    header_prefix: char,
+) -> MinicelResult<Vec<Token>> {
+    tokenize_with_spans(field, line_number, separator, header_prefix).map(|(tokens, _)| tokens)
+}
+
+/// Tokenize the given field like [`tokenize`], with the byte range of each token in the field.
+pub fn tokenize_with_spans(
+    field: &str,
+    line_number: usize,
+    separator: char,
+    header_prefix: char,
+) -> MinicelResult<(Vec<Token>, Vec<Span>)> {
+    let len = field.len();
+    let mut field = field.char_indices().peekable();
     let mut tokens = Vec::new();
-    while let Some(c) = field.peek() {
-        match c {
-            ';' => {
-                tokens.push(Token::Semicolon);
+    let mut spans = Vec::new();
+    while let Some((start, c)) = field.peek().copied() {
+        let token = match c {
+            c if c == ';' || c == separator => {
+                field.next();
+                Token::Semicolon
+            }
+            c if c == header_prefix => {
                 field.next();
+                read_header_name(&mut field, line_number, header_prefix)?
             }
             '(' => {
-                tokens.push(Token::LeftParenthesis);
                 field.next();
+                Token::LeftParenthesis
             }
             ')' => {
-                tokens.push(Token::RightParenthesis);
                 field.next();
+                Token::RightParenthesis
             }
             '[' => {
-                tokens.push(Token::LeftBracket);
                 field.next();
+                Token::LeftBracket
             }
             ']' => {
-                tokens.push(Token::RightBracket);
                 field.next();
+                Token::RightBracket
             }
-            '"' => {
+            ':' => {
                 field.next();
-                tokens.push(read_string(&mut field, line_number)?);
-            }
-            '0'..='9' | '-' => {
-                tokens.push(read_number(&mut field, line_number)?);
+                Token::Colon
             }
-            '_' | 'a'..='z' | 'A'..='Z' => {
-                tokens.push(read_identifier(&mut field));
+            '"' => {
+                field.next();
+                read_string(&mut field, line_number)?
             }
+            '0'..='9' | '-' | '.' => read_number(&mut field, line_number, separator)?,
+            '_' | 'a'..='z' | 'A'..='Z' => read_identifier(&mut field),
             c if c.is_whitespace() => {
                 field.next();
+                continue;
             }
             _ => {
                 return Err(MinicelError::new(
@@ -159,7 +206,100 @@ pub fn tokenize(field: &str, line_number: usize) -> MinicelResult<Vec<Token>> {
                     line_number,
                 ))
             }
+        };
+        tokens.push(token);
+        spans.push(start..field.peek().map_or(len, |(end, _)| *end));
+    }
+    Ok((tokens, spans))
+}
+
+/// Tokenize the given cell, returns `None` if it's not a formula. A formula cell starts with `=`,
+/// the cells starting with `'=` are escaped literals. The arguments are separated by `;` or the given separator,
+/// and the header-name references start with the given header prefix.
+///
+/// The spans are the byte ranges of the tokens in the given cell.
+pub fn tokenize_cell(
+    field: &str,
+    line_number: usize,
+    separator: char,
+    header_prefix: char,
+) -> MinicelResult<Option<(Vec<Token>, Vec<Span>)>> {
+    let formula = field.trim_start();
+    if !formula.starts_with('=') {
+        return Ok(None);
+    }
+    let formula = formula.trim_start_matches('=').trim_start();
+    let offset = field.len() - formula.len();
+    let (tokens, spans) = tokenize_with_spans(formula, line_number, separator, header_prefix)?;
+    let spans = spans
+        .into_iter()
+        .map(|span| span.start + offset..span.end + offset)
+        .collect();
+    Ok(Some((tokens, spans)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expression;
+
+    #[test]
+    fn number_literals_and_cells_agree() {
+        let accepted = ["1", "-1.5", ".5", "1.", "007", "1e3", "1E-3", "2.5e+2"];
+        let rejected = ["1_000", "1e", "1e+", "1.2.3", "-", ".", "--1", "1x", "0x10"];
+        for text in accepted.into_iter().chain(rejected) {
+            let literal = match tokenize(text, 1, ';', '$').as_deref() {
+                Ok([Token::Number(number)]) => Some(number.clone()),
+                _ => None,
+            };
+            let cell = match utils::parse_string_to_expression(text.to_owned(), true, false) {
+                Expression::Number(number) => Some(number),
+                _ => None,
+            };
+            assert_eq!(literal, cell, "{text}");
+            assert_eq!(literal.is_some(), accepted.contains(&text), "{text}");
+        }
+    }
+
+    #[test]
+    fn tokenizes_the_formula_cells() {
+        let tokens = tokenize_cell(" =sum(A1;2) ", 1, ';', '$')
+            .map_err(|err| err.message)
+            .unwrap()
+            .map(|(tokens, _)| tokens);
+        assert_eq!(
+            tokens,
+            Some(vec![
+                Token::Identifier("sum".to_owned()),
+                Token::LeftParenthesis,
+                Token::Identifier("A1".to_owned()),
+                Token::Semicolon,
+                Token::Number(BigDecimal::from(2)),
+                Token::RightParenthesis,
+            ])
+        );
+    }
+
+    #[test]
+    fn spans_the_tokens_in_the_cell() {
+        let (_, spans) = tokenize_cell(" = sum(A1; \"x\")", 1, ';', '$')
+            .map_err(|err| err.message)
+            .unwrap()
+            .unwrap();
+        assert_eq!(spans, vec![3..6, 6..7, 7..9, 9..10, 11..14, 14..15]);
+    }
+
+    #[test]
+    fn skips_the_non_formula_cells() {
+        for field in ["sum(A1;2)", "'=sum(A1;2)", "", "12"] {
+            assert_eq!(
+                tokenize_cell(field, 1, ';', '$')
+                    .map(|tokens| tokens.is_none())
+                    .map_err(|err| err.message),
+                Ok(true),
+                "{field}"
+            );
         }
+        assert!(tokenize_cell("=sum(\"a)", 1, ';', '$').is_err());
     }
-    Ok(tokens)
 }
@@ -0,0 +1,122 @@
+//! The typed values of the evaluated cells.
+
+use bigdecimal::BigDecimal;
+
+use crate::{ast::Expression, utils};
+
+/// The error codes of the cells that fail to evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A division by zero. `#DIV/0!`
+    DivisionByZero,
+    /// A call of an unknown function. `#NAME?`
+    UnknownFunction,
+    /// A reference to a row or a column that is beyond the sheet. `#REF!`
+    InvalidReference,
+    /// A formula that is not valid. `#SYNTAX!`
+    InvalidFormula,
+    /// Any other evaluation error, e.g. an argument of a wrong type. `#VALUE!`
+    InvalidValue,
+}
+
+/// The typed value of an evaluated cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(BigDecimal),
+    Text(String),
+    Bool(bool),
+    /// An empty cell.
+    Empty,
+    /// A cell that failed to evaluate.
+    Error(ErrorCode),
+    Array(Vec<Value>),
+}
+
+impl ErrorCode {
+    /// Returns the error code of the given spreadsheet representation. e.g. `#DIV/0!`
+    pub fn parse(text: &str) -> Option<Self> {
+        [
+            Self::DivisionByZero,
+            Self::UnknownFunction,
+            Self::InvalidReference,
+            Self::InvalidFormula,
+            Self::InvalidValue,
+        ]
+        .into_iter()
+        .find(|code| code.as_str() == text)
+    }
+
+    /// Returns the spreadsheet representation of the error code.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::DivisionByZero => "#DIV/0!",
+            Self::UnknownFunction => "#NAME?",
+            Self::InvalidReference => "#REF!",
+            Self::InvalidFormula => "#SYNTAX!",
+            Self::InvalidValue => "#VALUE!",
+        }
+    }
+}
+
+impl Value {
+    /// Returns the value of the given evaluated cell content, the `true` and `false` contents are
+    /// booleans if `booleans` is set and the `1,5` like contents are numbers if `decimal_comma` is set.
+    pub fn from_cell(content: String, booleans: bool, decimal_comma: bool) -> Self {
+        if content.is_empty() {
+            return Self::Empty;
+        }
+        Self::from_expression(utils::parse_string_to_expression(
+            content,
+            booleans,
+            decimal_comma,
+        ))
+    }
+
+    /// Returns the value of the given evaluated result of a cell, a text result (e.g. of a builtin
+    /// function) is read like the cell contents, see [`Value::from_cell`].
+    pub fn from_result(result: Expression, booleans: bool, decimal_comma: bool) -> Self {
+        match result {
+            Expression::String(content) => Self::from_cell(content, booleans, decimal_comma),
+            result => Self::from_expression(result),
+        }
+    }
+
+    /// Returns the value of the given evaluated expression.
+    pub fn from_expression(expression: Expression) -> Self {
+        match expression {
+            Expression::Number(number) => Self::Number(number),
+            Expression::Boolean(boolean) => Self::Bool(boolean),
+            Expression::Error(code) => Self::Error(code),
+            Expression::Array(array) => {
+                Self::Array(array.into_iter().map(Self::from_expression).collect())
+            }
+            expression => Self::Text(expression.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Number(number) => write!(
+                f,
+                "{}",
+                utils::format_number(number, utils::DEFAULT_PLAIN_NUMBER_LIMIT)
+            ),
+            Value::Text(text) => write!(f, "{text}"),
+            Value::Bool(boolean) => write!(f, "{boolean}"),
+            Value::Empty => Ok(()),
+            Value::Error(code) => write!(f, "{}", code.as_str()),
+            Value::Array(array) => {
+                write!(f, "[")?;
+                for (i, element) in array.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! The integration tests of the minicel binary.
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// Returns a new empty temporary directory for the given test.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("minicel-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn evaluates_the_given_input_into_the_given_output() {
+    let dir = temp_dir("run");
+    let (input, output) = (dir.join("input.csv"), dir.join("output.csv"));
+    fs::write(&input, "a,b,c\n1,2,=sum(A1;B1)\n3,4,=mul(A2;C1)\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(&input)
+        .arg(&output)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        "a,b,c\n1,2,3\n3,4,9\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn prints_the_help_message_without_the_paths() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage"));
+}
+
+#[test]
+fn fails_without_the_input_file() {
+    let dir = temp_dir("missing");
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(dir.join("missing.csv"))
+        .arg(dir.join("output.csv"))
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!dir.join("output.csv").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn progress_keeps_the_stdout_output_clean() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .args(["--progress", "--data", "a,b\\n1,=sum(A1;1)"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a,b\n1,2\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn lists_the_builtins() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg("--list-builtins")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for signature in ["sum(number...)", "concat(any...)", "if("] {
+        assert!(stdout.contains(signature), "{signature}");
+    }
+}
+
+#[test]
+fn prints_a_stable_checksum_of_the_output() {
+    let dir = temp_dir("checksum");
+    let (input, output) = (dir.join("input.csv"), dir.join("output.csv"));
+    fs::write(&input, "a,b\n1,=sum(A1;1)\n").unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(&input)
+        .arg(&output)
+        .arg("--checksum")
+        .output()
+        .unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a,b\n1,2\n");
+    assert_eq!(
+        String::from_utf8_lossy(&result.stderr).trim(),
+        format!(
+            "492d5ea496056f1a6a6592241032fab764c321596317930b4fa0e1e8bc3b7470  {}",
+            output.display()
+        )
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn writes_the_errors_to_stderr() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .args(["--data", "a,b\\n1,=sum(A1;x)"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Error"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ParseError"));
+}
+
+#[test]
+fn underlines_the_failed_argument() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .args(["--data", "a,b\\n1,\"=sum(1; \"\"x\"\")\""])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("at line: 2\n=sum(1; \"x\")\n        ^^^\n"));
+}
+
+#[test]
+fn rejects_rand_under_deterministic_without_a_seed() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_minicel"))
+            .args(args)
+            .args(["--data", "a,b\\n1,=rand()"])
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--deterministic"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("The rand function is nondeterministic, it requires a seed (`--seed`)"));
+
+    let (first, second) = (
+        run(&["--deterministic", "--seed", "7"]),
+        run(&["--deterministic", "--seed", "7"]),
+    );
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+}
+
+#[test]
+fn rejects_a_zero_byte_input_file() {
+    let dir = temp_dir("empty");
+    let (input, output) = (dir.join("input.csv"), dir.join("output.csv"));
+    fs::write(&input, "").unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(&input)
+        .arg(&output)
+        .output()
+        .unwrap();
+
+    assert_eq!(result.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("The input is empty"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn checks_the_assertions_after_the_run() {
+    let run = |assertions: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_minicel"));
+        command.args(["--data", "a,b\\n1,=sum(A1;1)"]);
+        for assertion in assertions {
+            command.args(["--assert", assertion]);
+        }
+        command.output().unwrap()
+    };
+
+    let passing = run(&["B1==2", "A1==1"]);
+    assert!(passing.status.success());
+    assert!(passing.stderr.is_empty());
+
+    let failing = run(&["B1==2", "B1==3"]);
+    assert_eq!(failing.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&failing.stderr),
+        "Assertion failed: B1\n  expected: 3\n  found:    2\n1 of 2 assertions failed\n"
+    );
+}
+
+#[test]
+fn evaluates_the_inline_data() {
+    let output = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .args(["--data", "a,b\\n1,=sum(A1;1)\\n2,=sum(A2;B1)"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a,b\n1,2\n2,4\n");
+
+    let dir = temp_dir("data");
+    let path = dir.join("output.csv");
+    let status = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .args(["--data", "a,b\\n1,=sum(A1;1)"])
+        .arg(&path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a,b\n1,2\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn formats_the_results_for_excel() {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let dir = temp_dir("excel");
+    let (output, reimported) = (dir.join("output.csv"), dir.join("reimported.csv"));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(fixtures.join("excel_input.csv"))
+        .arg(&output)
+        .args(["--format-profile", "excel"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let expected = fs::read_to_string(fixtures.join("excel_output.csv")).unwrap();
+    assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+
+    // The output has no formulas left, importing it again keeps it unchanged
+    let status = Command::new(env!("CARGO_BIN_EXE_minicel"))
+        .arg(&output)
+        .arg(&reimported)
+        .args(["--format-profile", "excel"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&reimported).unwrap(), expected);
+    fs::remove_dir_all(dir).unwrap();
+}